@@ -41,6 +41,14 @@ pub struct SessionConfig {
 	pub mean_authored_packet_period: Duration,
 }
 
+/// Error updating a configuration parameter.
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum ConfigErr {
+	/// The given loop cover proportion is not between 0 and 1.
+	#[error("Loop cover proportion ({0}) not between 0 and 1")]
+	BadLoopCoverProportion(f64),
+}
+
 /// Mixnet configuration.
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -50,7 +58,17 @@ pub struct Config {
 	/// The number of mixnodes to connect to when we are not a mixnode ourselves. When we are a
 	/// mixnode, we connect to all other mixnodes.
 	pub num_gateway_mixnodes: u32,
+	/// If not [`None`], a warning is logged whenever, in a session where we are not a mixnode,
+	/// the ratio of gateway mixnodes to mixnodes is below this value. This can happen when
+	/// `num_gateway_mixnodes` is small relative to the size of the mixnode set.
+	pub min_gateway_ratio: Option<f64>,
+	/// If `true`, additional gateway mixnodes are chosen to bring the ratio up to
+	/// `min_gateway_ratio` when it is below it. Has no effect if `min_gateway_ratio` is [`None`].
+	pub promote_on_shortage: bool,
 
+	/// Expected duration of each session. Only used to estimate when sessions will end; see
+	/// `Mixnet::time_until_session_expiry`. [`None`] if unknown.
+	pub session_duration: Option<Duration>,
 	/// The key-exchange secret key to use in session 0. This option is intended for testing
 	/// purposes only.
 	pub session_0_kx_secret: Option<KxSecret>,
@@ -61,6 +79,11 @@ pub struct Config {
 	/// participate in the mixnet during sessions in which we are a mixnode.
 	pub non_mixnode_session: Option<SessionConfig>,
 
+	/// Proportion of the authored packet queue capacity at which the application should stop
+	/// accepting new messages from users. See `Mixnet::authored_queue_backpressure_threshold`.
+	/// Must be between 0 and 1.
+	pub backpressure_fill_ratio: f64,
+
 	/// Maximum number of packets waiting for their forwarding delay to elapse. When at the limit,
 	/// any packets arriving that need forwarding will simply be dropped.
 	pub forward_packet_queue_capacity: usize,
@@ -73,6 +96,11 @@ pub struct Config {
 	/// packets or real packets). If this is not the same for all nodes, delay estimates may be
 	/// off.
 	pub loop_cover_proportion: f64,
+	/// If not [`None`], a loop cover packet will be sent in place of a drop cover/real packet
+	/// whenever the proportion of loop cover packets among recently authored packets falls below
+	/// this value. This guards against long runs of non-loop packets, which could delay detection
+	/// of misbehaving mixnodes. Should not be greater than `loop_cover_proportion`.
+	pub min_loop_cover_ratio: Option<f64>,
 	/// Generate cover packets? This option is intended for testing purposes only. It essentially
 	/// just drops all cover packets instead of sending them.
 	pub gen_cover_packets: bool,
@@ -89,6 +117,21 @@ pub struct Config {
 	pub max_incomplete_fragments: usize,
 	/// Maximum number of fragments per message. This should really be the same for all nodes!
 	pub max_fragments_per_message: usize,
+
+	/// Maximum number of requests posted with
+	/// [`post_request_with_reliability`](super::Mixnet::post_request_with_reliability) to
+	/// remember for retrying. When at the limit, the request with the earliest retry deadline is
+	/// forgotten.
+	pub max_retried_requests: usize,
+	/// Maximum number of times a request may be re-posted by
+	/// [`notify_request_timeout`](super::Mixnet::notify_request_timeout).
+	pub max_request_retries: usize,
+
+	/// Probability with which incoming packets and authored packets are randomly dropped, to
+	/// simulate packet loss. Must be between 0 and 1. This option is intended for testing
+	/// purposes only.
+	#[cfg(any(test, feature = "fault-injection"))]
+	pub packet_drop_probability: f64,
 }
 
 impl Default for Config {
@@ -97,7 +140,10 @@ impl Default for Config {
 			log_target: "mixnet",
 
 			num_gateway_mixnodes: 3,
+			min_gateway_ratio: None,
+			promote_on_shortage: false,
 
+			session_duration: None,
 			session_0_kx_secret: None,
 			mixnode_session: SessionConfig {
 				authored_packet_queue: AuthoredPacketQueueConfig {
@@ -120,11 +166,14 @@ impl Default for Config {
 				mean_authored_packet_period: Duration::from_millis(1000),
 			}),
 
+			backpressure_fill_ratio: 0.8,
+
 			forward_packet_queue_capacity: 300,
 			mean_forwarding_delay: Duration::from_secs(1),
 			per_hop_net_delay: Duration::from_millis(300),
 
 			loop_cover_proportion: 0.25,
+			min_loop_cover_ratio: None,
 			gen_cover_packets: true,
 			num_hops: MAX_HOPS,
 
@@ -132,6 +181,53 @@ impl Default for Config {
 			max_incomplete_messages: 2000,
 			max_incomplete_fragments: 2000,
 			max_fragments_per_message: 25,
+
+			max_retried_requests: 100,
+			max_request_retries: 3,
+
+			#[cfg(any(test, feature = "fault-injection"))]
+			packet_drop_probability: 0.0,
 		}
 	}
 }
+
+impl Config {
+	/// Returns the default configuration, with some parameters scaled according to the expected
+	/// number of mixnodes in each session. All nodes should use the same hint, as some of the
+	/// affected parameters (eg `num_hops`) should really be the same for all nodes.
+	pub fn with_network_size_hint(num_mixnodes: usize) -> Self {
+		let default = Self::default();
+
+		// More hops are needed to mix effectively in larger networks. log2 of the number of
+		// mixnodes, clamped to between 3 and MAX_HOPS.
+		let num_hops = (num_mixnodes.max(1).ilog2() as usize).clamp(3, MAX_HOPS);
+
+		// Each forwarded packet spends a forwarding delay in the queue at each hop but the last,
+		// so the expected queue occupancy is proportional to the number of hops. The default
+		// capacity allows for 50 packets per hop.
+		let forward_packet_queue_capacity = 50 * num_hops;
+
+		// Can't connect to more gateway mixnodes than exist
+		let num_gateway_mixnodes = default
+			.num_gateway_mixnodes
+			.min(num_mixnodes.try_into().unwrap_or(u32::MAX))
+			.max(1);
+
+		Self { num_gateway_mixnodes, forward_packet_queue_capacity, num_hops, ..default }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn with_network_size_hint() {
+		let config = Config::with_network_size_hint(2);
+		assert_eq!((config.num_hops, config.num_gateway_mixnodes), (3, 2));
+		let config = Config::with_network_size_hint(20);
+		assert_eq!((config.num_hops, config.num_gateway_mixnodes), (4, 3));
+		assert_eq!(config.forward_packet_queue_capacity, 200);
+		assert_eq!(Config::with_network_size_hint(10_000).num_hops, MAX_HOPS);
+	}
+}
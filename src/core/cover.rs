@@ -22,28 +22,38 @@
 
 use super::{
 	packet_queues::AddressedPacket,
-	sphinx::build_cover_packet,
+	sphinx::{build_cover_packet, MixnodeIndex},
 	topology::{NetworkStatus, RouteGenerator, RouteKind, Topology, TopologyErr},
 	util::default_boxed_array,
 };
 use arrayvec::ArrayVec;
 use rand::{CryptoRng, Rng};
+use std::collections::VecDeque;
 
-#[derive(PartialEq, Eq)]
+/// Kind of cover packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CoverKind {
+	/// Drop cover packets are sent to a random mixnode, which simply discards them.
 	Drop,
+	/// Loop cover packets are routed back to the local node.
 	Loop,
 }
 
+/// Generate a cover packet. If `first_mixnode_index` is not [`None`], the packet will be sent to
+/// the mixnode with this index first.
 pub fn gen_cover_packet<X>(
 	rng: &mut (impl Rng + CryptoRng),
 	topology: &Topology<X>,
 	ns: &dyn NetworkStatus,
 	kind: CoverKind,
 	num_hops: usize,
+	first_mixnode_index: Option<MixnodeIndex>,
 ) -> Result<AddressedPacket, TopologyErr> {
 	// Generate route
-	let route_generator = RouteGenerator::new(topology, ns);
+	let mut route_generator = RouteGenerator::new(topology, ns);
+	if let Some(first_mixnode_index) = first_mixnode_index {
+		route_generator.force_first_index(first_mixnode_index);
+	}
 	let route_kind = match kind {
 		CoverKind::Drop => RouteKind::ToMixnode(route_generator.choose_destination_index(rng)?),
 		CoverKind::Loop => RouteKind::Loop,
@@ -65,3 +75,57 @@ pub fn gen_cover_packet<X>(
 
 	Ok(AddressedPacket { peer_id, packet })
 }
+
+/// Number of authored packets tracked by [`LoopCoverWindow`].
+const LOOP_COVER_WINDOW_SIZE: usize = 64;
+
+/// Tracks which of the most recently authored packets were loop cover packets.
+#[derive(Default)]
+pub struct LoopCoverWindow {
+	/// One entry per authored packet, most recent at the back. `true` for loop cover packets.
+	is_loop: VecDeque<bool>,
+	num_loop: usize,
+}
+
+impl LoopCoverWindow {
+	/// Record an authored packet.
+	pub fn push(&mut self, kind: &CoverKind) {
+		if (self.is_loop.len() == LOOP_COVER_WINDOW_SIZE) &&
+			self.is_loop.pop_front().expect("Window is full")
+		{
+			self.num_loop -= 1;
+		}
+		let is_loop = *kind == CoverKind::Loop;
+		self.is_loop.push_back(is_loop);
+		if is_loop {
+			self.num_loop += 1;
+		}
+	}
+
+	/// Returns the proportion of packets in the window that were loop cover packets, or [`None`]
+	/// if the window is not yet full.
+	pub fn loop_proportion(&self) -> Option<f64> {
+		(self.is_loop.len() == LOOP_COVER_WINDOW_SIZE)
+			.then(|| (self.num_loop as f64) / (LOOP_COVER_WINDOW_SIZE as f64))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn loop_cover_window() {
+		let mut window = LoopCoverWindow::default();
+		for _ in 0..LOOP_COVER_WINDOW_SIZE - 1 {
+			window.push(&CoverKind::Loop);
+		}
+		assert_eq!(window.loop_proportion(), None);
+		window.push(&CoverKind::Drop);
+		assert_eq!(window.loop_proportion(), Some(63.0 / 64.0));
+		for _ in 0..LOOP_COVER_WINDOW_SIZE / 2 {
+			window.push(&CoverKind::Drop);
+		}
+		assert_eq!(window.loop_proportion(), Some(31.0 / 64.0));
+	}
+}
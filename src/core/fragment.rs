@@ -27,11 +27,18 @@ use super::{
 use arrayref::{array_mut_ref, array_refs, mut_array_refs};
 use hashlink::{linked_hash_map::Entry, LinkedHashMap};
 use log::{debug, log, Level};
-use std::cmp::{max, min};
+use std::{
+	cmp::{max, min},
+	time::{Duration, Instant},
+};
 
 /// Size in bytes of a [`MessageId`].
 pub const MESSAGE_ID_SIZE: usize = 16;
 /// Message identifier. Should be randomly generated. Attached to fragments to enable reassembly.
+///
+/// Fragments are carried in the encrypted packet payload, so there is no way of determining the
+/// message ID of a packet without fully unwrapping it. This is deliberate: an observer should not
+/// be able to link packets belonging to the same message.
 pub type MessageId = [u8; MESSAGE_ID_SIZE];
 const FRAGMENT_INDEX_SIZE: usize = 2;
 type FragmentIndex = u16;
@@ -170,11 +177,17 @@ struct IncompleteMessage {
 	fragments: Vec<Option<Box<Fragment>>>,
 	/// Count of [`Some`] in `fragments`.
 	num_received_fragments: usize,
+	/// When the first fragment was received. This is the oldest fragment of the message.
+	first_received_at: Instant,
 }
 
 impl IncompleteMessage {
 	fn new(num_fragments: usize) -> Self {
-		Self { fragments: vec![None; num_fragments], num_received_fragments: 0 }
+		Self {
+			fragments: vec![None; num_fragments],
+			num_received_fragments: 0,
+			first_received_at: Instant::now(),
+		}
 	}
 
 	/// Attempt to insert `fragment`, which must be a valid fragment (checked by
@@ -246,6 +259,23 @@ impl FragmentAssembler {
 		}
 	}
 
+	/// Returns the maximum size of a message split into at most `max_fragments` fragments. SURBs
+	/// attached to the message take up space that would otherwise be used for data.
+	pub fn max_message_size_bytes(max_fragments: usize) -> usize {
+		min(max_fragments, (FragmentIndex::MAX as usize) + 1).saturating_mul(FRAGMENT_PAYLOAD_SIZE)
+	}
+
+	/// Returns how long ago the oldest fragment across all incomplete messages was received, or
+	/// [`None`] if there are no incomplete messages. Fragments lingering for a long time may
+	/// indicate that senders are abandoning partial messages.
+	pub fn age_of_oldest_fragment(&self, now: Instant) -> Option<Duration> {
+		self.incomplete_messages
+			.values()
+			.map(|message| message.first_received_at)
+			.min()
+			.map(|first_received_at| now.saturating_duration_since(first_received_at))
+	}
+
 	fn need_eviction(&self) -> bool {
 		(self.incomplete_messages.len() > self.max_incomplete_messages) ||
 			(self.num_incomplete_fragments > self.max_incomplete_fragments)
@@ -330,6 +360,11 @@ pub struct FragmentBlueprint<'a> {
 }
 
 impl<'a> FragmentBlueprint<'a> {
+	/// Returns the index of the fragment within the message.
+	pub fn index(&self) -> usize {
+		self.index as usize
+	}
+
 	pub fn write_except_surbs(&self, fragment: &mut Fragment) {
 		let (message_id, last_index, index, data_size, num_surbs, payload) = mut_array_refs![
 			fragment,
@@ -368,7 +403,7 @@ impl<'a> FragmentBlueprint<'a> {
 }
 
 // TODO Use usize::div_ceil when this is stabilised
-fn div_ceil(x: usize, y: usize) -> usize {
+pub fn div_ceil(x: usize, y: usize) -> usize {
 	if x == 0 {
 		0
 	} else {
@@ -488,6 +523,39 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn max_message_size() {
+		let max_size = FragmentAssembler::max_message_size_bytes(3);
+		let data = vec![0; max_size];
+		let fragments = fragment_blueprints(&[0; MESSAGE_ID_SIZE], data.as_slice().into(), 0);
+		assert_eq!(fragments.unwrap().len(), 3);
+		let data = vec![0; max_size + 1];
+		let fragments = fragment_blueprints(&[0; MESSAGE_ID_SIZE], data.as_slice().into(), 0);
+		assert_eq!(fragments.unwrap().len(), 4);
+
+		// SURBs eat into the space for data
+		let data = vec![0; max_size - (2 * SURB_SIZE)];
+		let fragments = fragment_blueprints(&[0; MESSAGE_ID_SIZE], data.as_slice().into(), 2);
+		assert_eq!(fragments.unwrap().len(), 3);
+
+		assert_eq!(
+			FragmentAssembler::max_message_size_bytes(usize::MAX),
+			((FragmentIndex::MAX as usize) + 1) * FRAGMENT_PAYLOAD_SIZE
+		);
+	}
+
+	#[test]
+	fn age_of_oldest_fragment() {
+		let mut fa = FragmentAssembler::new(1, usize::MAX, usize::MAX);
+		assert_eq!(fa.age_of_oldest_fragment(Instant::now()), None);
+		let fragments = no_surb_fragments(&[0; MESSAGE_ID_SIZE], &[0; 3000]);
+		assert!(fa.insert(&fragments[0], LOG_TARGET).is_none());
+		let later = Instant::now() + Duration::from_secs(5);
+		assert!(fa.age_of_oldest_fragment(later).unwrap() >= Duration::from_secs(5));
+		assert!(fa.insert(&fragments[1], LOG_TARGET).is_some());
+		assert_eq!(fa.age_of_oldest_fragment(later), None);
+	}
+
 	#[test]
 	fn create_too_large() {
 		let too_large = vec![0; (((FragmentIndex::MAX as usize) + 1) * FRAGMENT_PAYLOAD_SIZE) + 1];
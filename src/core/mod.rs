@@ -28,8 +28,10 @@ mod cover;
 mod fragment;
 mod kx_pair;
 mod packet_queues;
+mod packet_writer;
 mod replay_filter;
 mod request_builder;
+mod retry_tracker;
 mod scattered;
 mod sessions;
 mod sphinx;
@@ -38,41 +40,56 @@ mod topology;
 mod util;
 
 pub use self::{
-	config::{Config, SessionConfig},
+	config::{Config, ConfigErr, SessionConfig},
+	cover::CoverKind,
 	fragment::{MessageId, MESSAGE_ID_SIZE},
-	packet_queues::AddressedPacket,
+	packet_queues::{
+		AddressedPacket, AuthoredQueueEvictionPolicy, EvictOldest, ForwardQueueDeadlineStats,
+		ForwardQueueStats, NoEviction, Reservation, WakerToken, MAX_AUTHORED_QUEUE_CAPACITY,
+	},
+	packet_writer::PacketWriter,
 	scattered::Scattered,
-	sessions::{RelSessionIndex, SessionIndex, SessionPhase, SessionStatus},
+	sessions::{RelSessionIndex, SessionIndex, SessionPhase, SessionSlotKind, SessionStatus},
 	sphinx::{
-		Delay, KxPublic, KxSecret, MixnodeIndex, Packet, PeerId, RawMixnodeIndex, Surb,
-		KX_PUBLIC_SIZE, MAX_HOPS, MAX_MIXNODE_INDEX, PACKET_SIZE, PEER_ID_SIZE, SURB_SIZE,
+		Delay, KxPublic, KxSecret, MixnodeIndex, Packet, PeerId, RawMixnodeIndex, Surb, SurbId,
+		KX_PUBLIC_SIZE, MAX_HOPS, MAX_MIXNODE_INDEX, PACKET_SIZE, PEER_ID_SIZE, SURB_ID_SIZE,
+		SURB_SIZE,
 	},
-	topology::{Mixnode, NetworkStatus, TopologyErr},
+	topology::{Mixnode, NetworkStatus, Topology, TopologyErr},
 };
+#[cfg(feature = "json")]
+pub use self::topology::JsonErr;
+#[cfg(feature = "scale")]
+pub use self::topology::ScaleErr;
+#[cfg(feature = "test-utils")]
+pub use self::sphinx::{Action, Target};
+#[cfg(not(feature = "test-utils"))]
+use self::sphinx::Action;
 use self::{
-	cover::{gen_cover_packet, CoverKind},
+	cover::{gen_cover_packet, LoopCoverWindow},
 	fragment::{fragment_blueprints, FragmentAssembler},
 	kx_pair::KxPair,
-	packet_queues::{AuthoredPacketQueue, CheckSpaceErr, ForwardPacketQueue},
-	replay_filter::ReplayFilter,
+	packet_queues::{AuthoredPacketQueue, CheckSpaceErr, FillNotifier, ForwardPacketQueue},
+	replay_filter::{ReplayFilter, ReplayTag},
 	request_builder::RequestBuilder,
+	retry_tracker::RetryTracker,
 	sessions::{Session, SessionSlot, Sessions},
 	sphinx::{
-		complete_reply_packet, decrypt_reply_payload, kx_public, mut_payload_data, peel, Action,
-		PeelErr, PAYLOAD_DATA_SIZE, PAYLOAD_SIZE,
+		complete_reply_packet, decrypt_reply_payload, kx_public, mut_payload_data, peel, PeelErr,
+		PAYLOAD_DATA_SIZE, PAYLOAD_SIZE,
 	},
 	surb_keystore::SurbKeystore,
-	topology::Topology,
 	util::default_boxed_array,
 };
 use arrayref::{array_mut_ref, array_ref};
 use arrayvec::ArrayVec;
 use bitflags::bitflags;
 use either::Either;
-use log::{debug, info, trace};
-use rand::Rng;
+use log::{debug, info, trace, warn};
+use rand::{CryptoRng, Rng};
 use std::{
 	cmp::{max, min},
+	fmt,
 	time::{Duration, Instant},
 };
 
@@ -143,6 +160,111 @@ pub enum PostErr {
 	/// Bad SURB.
 	#[error("Bad SURB")]
 	BadSurb,
+	/// The reservation was not made for the session's authored packet queue.
+	#[error("Reservation is for a different authored packet queue")]
+	BadReservation,
+}
+
+/// Returned by [`Mixnet::handle_packet_with_deadline`] and
+/// [`Mixnet::handle_packet_with_timeout`] if the deadline passes before the packet has been
+/// handled.
+#[derive(Debug, thiserror::Error)]
+#[error("Timed out handling packet")]
+pub struct Timeout;
+
+/// Error restoring an authored packet queue. See
+/// [`Mixnet::authored_queue_decode_from_persistence`].
+#[cfg(feature = "scale")]
+#[derive(Debug, thiserror::Error)]
+pub enum PersistenceErr {
+	/// The data could not be decoded.
+	#[error("Bad SCALE-encoded data: {0}")]
+	Decode(#[from] codec::Error),
+	/// An encoded packet has the wrong size.
+	#[error("Bad packet size ({0}, expected {PACKET_SIZE})")]
+	BadPacketSize(usize),
+	/// An encoded fragment index is out of range.
+	#[error("Bad fragment index ({0})")]
+	BadFragmentIndex(u64),
+	/// The session is not active.
+	#[error("Session not active")]
+	SessionNotActive,
+	/// Not enough space in the authored packet queue.
+	#[error("There is not enough space in the authored packet queue")]
+	NotEnoughSpaceInQueue,
+}
+
+/// Generate a drop cover packet with the same first hop as `packet`. Returns [`None`] if this is
+/// not possible.
+fn gen_drop_cover_packet_like<X>(
+	rng: &mut (impl Rng + CryptoRng),
+	topology: &Topology<X>,
+	ns: &dyn NetworkStatus,
+	num_hops: usize,
+	packet: &AddressedPacket,
+) -> Option<AddressedPacket> {
+	let first_mixnode_index = topology.mixnode_index_from_peer_id(&packet.peer_id)?;
+	gen_cover_packet(rng, topology, ns, CoverKind::Drop, num_hops, Some(first_mixnode_index)).ok()
+}
+
+/// Authored packet queue integrity error. See [`Mixnet::authored_queue_verify_integrity`].
+#[cfg(debug_assertions)]
+#[derive(Debug, thiserror::Error)]
+pub enum IntegrityErr {
+	/// The session is not active.
+	#[error("Session not active")]
+	SessionNotActive,
+	/// A packet in the queue has an all-zero destination peer ID.
+	#[error("Packet {0} in queue has a null peer ID")]
+	NullPeerId(usize),
+	/// A packet in the queue is destined for a peer that is not a mixnode in the session.
+	#[error("Packet {position} in queue is destined for unknown peer {peer_id:x?}")]
+	UnknownPeerId {
+		/// Position of the packet in the queue.
+		position: usize,
+		/// Destination of the packet.
+		peer_id: PeerId,
+	},
+	/// An internal queue invariant does not hold.
+	#[error("Queue invariant violated: {0}")]
+	Invariant(&'static str),
+}
+
+/// Error changing the capacity of an authored packet queue. See
+/// [`Mixnet::authored_queue_set_capacity`].
+#[derive(Debug, thiserror::Error)]
+pub enum CapacityErr {
+	/// The session is not active.
+	#[error("Session not active")]
+	SessionNotActive,
+	/// The requested capacity is greater than [`MAX_AUTHORED_QUEUE_CAPACITY`].
+	#[error("Capacity {0} is too large (max {MAX_AUTHORED_QUEUE_CAPACITY})")]
+	TooLarge(usize),
+}
+
+/// Why packets are being dropped from an authored packet queue. See
+/// [`Mixnet::authored_queue_drop_by_destination`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropReason {
+	/// The destination peer has been banned.
+	PeerBanned,
+	/// The destination peer has disconnected.
+	PeerDisconnected,
+	/// The session is ending.
+	SessionEnding,
+	/// Space is needed in the queue.
+	CapacityPressure,
+}
+
+impl fmt::Display for DropReason {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::PeerBanned => write!(fmt, "Peer banned"),
+			Self::PeerDisconnected => write!(fmt, "Peer disconnected"),
+			Self::SessionEnding => write!(fmt, "Session ending"),
+			Self::CapacityPressure => write!(fmt, "Capacity pressure"),
+		}
+	}
 }
 
 fn post_session<X>(
@@ -185,11 +307,13 @@ impl From<CheckSpaceErr> for PostErr {
 /// queue to get dispatched plus the time taken for all reply packets to get through the authored
 /// packet queue at the far end.
 fn estimate_authored_packet_queue_delay<X>(config: &Config, session: &Session<X>) -> Duration {
+	let loop_cover_proportion =
+		config.loop_cover_proportion.max(config.min_loop_cover_ratio.unwrap_or(0.0));
 	let rate_mul =
 		// When transitioning between sessions, the rate is halved
 		0.5 *
 		// Loop cover packets are never replaced with packets from the authored packet queue
-		(1.0 - config.loop_cover_proportion);
+		(1.0 - loop_cover_proportion);
 	let request_period = session.mean_authored_packet_period.div_f64(rate_mul);
 	let request_len = session.authored_packet_queue.len();
 	// Assume that the destination mixnode is using the same configuration as us
@@ -231,6 +355,15 @@ fn estimate_authored_packet_queue_delay<X>(config: &Config, session: &Session<X>
 	s.mul_f64(4.92582 + (3.87809 * (n + (r * r * r * m)).sqrt()) + n + (r * m))
 }
 
+/// Options for [`Mixnet::post_request_with_options`].
+#[derive(Clone, Debug, Default)]
+pub struct PostOptions {
+	/// Tag to associate with each of the request's packets while they are in the authored packet
+	/// queue. Returned by [`Mixnet::authored_queue_tagged_pop`]. This is purely for the caller's
+	/// benefit; it is never sent over the network.
+	pub tag: u64,
+}
+
 /// Metrics that can be used to estimate a request's round-trip time.
 pub struct RequestMetrics {
 	/// The maximum number of hops for any of the fragments to reach the destination, plus the
@@ -256,6 +389,41 @@ impl RequestMetrics {
 	}
 }
 
+/// A snapshot of the state of the sessions and queues, for diagnostics. See
+/// [`Mixnet::sessions_summary`].
+#[derive(Clone, Debug)]
+pub struct SessionsSummary {
+	/// Index of the current session.
+	pub current_session_index: SessionIndex,
+	/// Current session phase.
+	pub current_phase: SessionPhase,
+	/// State of the current session slot.
+	pub current_session_state: SessionSlotKind,
+	/// State of the previous session slot.
+	pub prev_session_state: SessionSlotKind,
+	/// Length of the authored packet queue for the current session, if it is active.
+	pub current_authored_queue_len: Option<usize>,
+	/// Length of the authored packet queue for the previous session, if it is active.
+	pub prev_authored_queue_len: Option<usize>,
+	/// Length of the forward packet queue.
+	pub forward_queue_len: usize,
+	/// Number of SURBs we are holding payload encryption keys for.
+	pub surb_keystore_len: usize,
+}
+
+/// How long the packets currently in an authored packet queue have been waiting. See
+/// [`Mixnet::authored_queue_latency_stats`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LatencyStats {
+	/// Shortest time any packet has been waiting.
+	pub min_wait: Duration,
+	/// Longest time any packet has been waiting. This is normally the packet at the head of the
+	/// queue.
+	pub max_wait: Duration,
+	/// Mean time the packets have been waiting.
+	pub mean_wait: Duration,
+}
+
 bitflags! {
 	/// Flags to indicate events that have occurred. Note that these may be set spuriously.
 	pub struct Events: u32 {
@@ -281,6 +449,8 @@ pub struct Mixnet<X> {
 
 	/// Index and phase of current session.
 	session_status: SessionStatus,
+	/// When the current session started. [`None`] if we did not see it start.
+	current_session_start: Option<Instant>,
 	/// Current and previous sessions.
 	sessions: Sessions<X>,
 	/// Key-exchange key pair for the next session.
@@ -288,12 +458,24 @@ pub struct Mixnet<X> {
 
 	/// Queue of packets to be forwarded, after some delay.
 	forward_packet_queue: ForwardPacketQueue,
+	/// Decides which packets to evict from the authored packet queues to make space for new
+	/// messages.
+	authored_queue_eviction_policy: Box<dyn AuthoredQueueEvictionPolicy>,
+	/// Shared by the authored packet queues of all sessions. See
+	/// [`authored_queue_fill_event`](Self::authored_queue_fill_event).
+	authored_queue_fill_notifier: FillNotifier,
+	/// Kinds of the most recently authored packets, for enforcing
+	/// [`Config::min_loop_cover_ratio`].
+	loop_cover_window: LoopCoverWindow,
 
 	/// Keystore for SURB payload encryption keys.
 	surb_keystore: SurbKeystore,
 	/// Reassembles fragments into messages. Note that for simplicity there is just one assembler
 	/// for everything (requests and replies across all sessions).
 	fragment_assembler: FragmentAssembler,
+	/// Requests posted with [`post_request_with_reliability`](Self::post_request_with_reliability)
+	/// which have not yet been replied to.
+	retry_tracker: RetryTracker,
 
 	/// Flags to indicate events that have occurred.
 	events: Events,
@@ -317,28 +499,115 @@ impl<X> Mixnet<X> {
 			config.max_incomplete_fragments,
 			config.max_fragments_per_message,
 		);
+		let retry_tracker = RetryTracker::new(config.max_retried_requests);
 
 		Self {
 			config,
 
 			session_status: SessionStatus { current_index: 0, phase: SessionPhase::CoverToCurrent },
+			current_session_start: None,
 			sessions,
 			next_kx_pair: None,
 
 			forward_packet_queue,
+			authored_queue_eviction_policy: Box::new(NoEviction),
+			authored_queue_fill_notifier: FillNotifier::default(),
+			loop_cover_window: LoopCoverWindow::default(),
 
 			surb_keystore,
 			fragment_assembler,
+			retry_tracker,
 
 			events: Events::empty(),
 		}
 	}
 
+	/// Returns the configuration passed to [`new`](Self::new).
+	pub fn config(&self) -> &Config {
+		&self.config
+	}
+
+	/// Returns a mutable reference to the configuration. Note that some configuration options are
+	/// only read in [`new`](Self::new) or when sessions are created, so changes will not
+	/// necessarily take effect immediately. This is intended for testing purposes only.
+	#[cfg(any(test, feature = "test-utils"))]
+	pub fn config_mut(&mut self) -> &mut Config {
+		&mut self.config
+	}
+
 	/// Returns the current session index and phase.
 	pub fn session_status(&self) -> SessionStatus {
 		self.session_status
 	}
 
+	/// Returns an estimate of the time remaining until the specified session ends. The previous
+	/// session has always already ended, so for it this returns zero. Returns [`None`] if
+	/// [`Config::session_duration`] is not set, or if we did not see the current session start
+	/// (eg because it started before this `Mixnet` was created).
+	pub fn time_until_session_expiry(
+		&self,
+		rel_session_index: RelSessionIndex,
+		now: Instant,
+	) -> Option<Duration> {
+		let session_duration = self.config.session_duration?;
+		match rel_session_index {
+			RelSessionIndex::Current => {
+				let end = self.current_session_start? + session_duration;
+				Some(end.saturating_duration_since(now))
+			},
+			RelSessionIndex::Prev => Some(Duration::ZERO),
+		}
+	}
+
+	/// Returns a snapshot of the state of the sessions and queues, for diagnostics.
+	pub fn sessions_summary(&self) -> SessionsSummary {
+		let authored_queue_len = |rel_session_index| {
+			self.sessions[rel_session_index]
+				.as_option()
+				.map(|session| session.authored_packet_queue.len())
+		};
+		SessionsSummary {
+			current_session_index: self.session_status.current_index,
+			current_phase: self.session_status.phase,
+			current_session_state: self.sessions.current.kind(),
+			prev_session_state: self.sessions.prev.kind(),
+			current_authored_queue_len: authored_queue_len(RelSessionIndex::Current),
+			prev_authored_queue_len: authored_queue_len(RelSessionIndex::Prev),
+			forward_queue_len: self.forward_packet_queue.len(),
+			surb_keystore_len: self.surb_keystore.len(),
+		}
+	}
+
+	/// Returns how long ago the oldest fragment of any incomplete message was received, or
+	/// [`None`] if there are no incomplete messages. This can be used to monitor memory pressure;
+	/// long-lived incomplete messages may indicate that senders are abandoning partial messages.
+	pub fn age_of_oldest_incomplete_fragment(&self) -> Option<Duration> {
+		self.fragment_assembler.age_of_oldest_fragment(Instant::now())
+	}
+
+	/// Returns statistics on the forward packet queue, for monitoring. This is O(n) in the queue
+	/// length.
+	pub fn forward_queue_statistics(&self) -> ForwardQueueStats {
+		self.forward_packet_queue.statistics(Instant::now())
+	}
+
+	/// Returns percentiles of the times until the packets in the forward packet queue should be
+	/// sent, or [`None`] if the queue is empty. Operators can use this to check that the queueing
+	/// delay distribution matches [`Config::mean_forwarding_delay`]. This is O(n log n) in the
+	/// queue length.
+	pub fn forward_queue_deadline_statistics(&self) -> Option<ForwardQueueDeadlineStats> {
+		self.forward_packet_queue.deadline_statistics(Instant::now())
+	}
+
+	/// Release any memory not currently needed by the forward packet queue. This has no
+	/// observable effect on behaviour; it may be worth calling during periods of low traffic in
+	/// long-running nodes. As with [`authored_queue_compact`](Self::authored_queue_compact), the
+	/// storage is normally allocated up front, so compacting will typically result in
+	/// reallocations later.
+	pub fn forward_queue_compact(&mut self) {
+		self.forward_packet_queue.compact();
+	}
+
 	/// Sets the current session index and phase. The current and previous mixnodes may need to be
 	/// provided after calling this; see [`maybe_set_mixnodes`](Self::maybe_set_mixnodes).
 	pub fn set_session_status(&mut self, session_status: SessionStatus) {
@@ -348,6 +617,11 @@ impl<X> Mixnet<X> {
 
 		// Shift sessions when current session index changes
 		if self.session_status.current_index != session_status.current_index {
+			// We only know when the session started if we saw the previous session end
+			let saw_start =
+				session_status.current_index == self.session_status.current_index.wrapping_add(1);
+			self.current_session_start = saw_start.then(Instant::now);
+
 			let next_session = std::mem::take(&mut self.next_kx_pair)
 				.map_or(SessionSlot::Empty, SessionSlot::KxPair);
 			match session_status.current_index.saturating_sub(self.session_status.current_index) {
@@ -377,6 +651,13 @@ impl<X> Mixnet<X> {
 			self.sessions.prev = SessionSlot::Disabled;
 		}
 
+		// Forget requests for sessions which can no longer be posted in
+		let first_index = match self.sessions.prev {
+			SessionSlot::Disabled => session_status.current_index,
+			_ => session_status.current_index.saturating_sub(1),
+		};
+		self.retry_tracker.remove_sessions_before(first_index);
+
 		// For simplicity just assume these have changed. This should happen at most once a minute
 		// or so.
 		self.events |=
@@ -387,6 +668,30 @@ impl<X> Mixnet<X> {
 		info!(target: self.config.log_target, "Session status changed: {session_status}");
 	}
 
+	/// Discard all session state, including the authored packet queues, the forward packet queue,
+	/// and any requests awaiting retry, without recreating the `Mixnet`. This is intended for
+	/// recovering from errors. The configuration, the session status, the SURB keystore, and the
+	/// key-exchange keys for the next session are kept. Note that the key-exchange keys for the
+	/// current and previous sessions are lost, so packets for these sessions can no longer be
+	/// handled.
+	///
+	/// The current and previous session slots are left empty (or disabled, if the previous
+	/// session is not needed in the current phase). They can be refilled by calling
+	/// [`maybe_set_mixnodes`](Self::maybe_set_mixnodes) as usual. All event flags are set.
+	pub fn drop_all_sessions(&mut self) {
+		self.sessions = Sessions { current: SessionSlot::Empty, prev: SessionSlot::Empty };
+		if !self.session_status.phase.need_prev() || (self.session_status.current_index == 0) {
+			self.sessions.prev = SessionSlot::Disabled;
+		}
+		self.current_session_start = None;
+		self.forward_packet_queue =
+			ForwardPacketQueue::new(self.config.forward_packet_queue_capacity);
+		self.retry_tracker = RetryTracker::new(self.config.max_retried_requests);
+		self.loop_cover_window = LoopCoverWindow::default();
+		self.events = Events::all();
+		info!(target: self.config.log_target, "Dropped all sessions");
+	}
+
 	/// Sets the mixnodes for the specified session, if they are needed. If `mixnodes()` returns
 	/// `Err(MixnodesErr::Permanent)`, the session slot will be disabled, and later calls to
 	/// `maybe_set_mixnodes` for the session will return immediately. If `mixnodes()` returns
@@ -438,8 +743,23 @@ impl<X> Mixnet<X> {
 		};
 
 		// Build Topology struct
-		let topology =
+		let num_mixnodes = mixnodes.len();
+		let mut topology =
 			Topology::new(&mut rng, mixnodes, kx_pair.public(), self.config.num_gateway_mixnodes);
+		if let Some(min_gateway_ratio) = self.config.min_gateway_ratio {
+			let ratio = topology.gateway_to_mixnode_ratio();
+			if !topology.is_mixnode() && (ratio < min_gateway_ratio) {
+				warn!(
+					target: self.config.log_target,
+					"Session {session_index}: Gateway ratio {ratio} below minimum {min_gateway_ratio}"
+				);
+				if self.config.promote_on_shortage {
+					let num_gateway_mixnodes =
+						(min_gateway_ratio * (num_mixnodes as f64)).ceil() as u32;
+					topology.resize_gateway_set(&mut rng, num_gateway_mixnodes);
+				}
+			}
+		}
 
 		// Determine session config
 		let config = if topology.is_mixnode() {
@@ -463,8 +783,12 @@ impl<X> Mixnet<X> {
 		*session = SessionSlot::Full(Session {
 			kx_pair,
 			topology,
-			authored_packet_queue: AuthoredPacketQueue::new(config.authored_packet_queue),
+			authored_packet_queue: AuthoredPacketQueue::with_fill_notifier(
+				config.authored_packet_queue,
+				self.authored_queue_fill_notifier.clone(),
+			),
 			mean_authored_packet_period: config.mean_authored_packet_period,
+			clock_skew: Duration::ZERO,
 			replay_filter: ReplayFilter::new(&mut rng),
 		});
 
@@ -472,6 +796,83 @@ impl<X> Mixnet<X> {
 			Events::RESERVED_PEERS_CHANGED | Events::NEXT_AUTHORED_PACKET_DEADLINE_CHANGED;
 	}
 
+	/// Disable the mixnet for the specified session, eg because the session is known to be
+	/// compromised. Any packets in the session's authored packet queue are discarded, as is the
+	/// session's replay filter. Later calls to [`maybe_set_mixnodes`](Self::maybe_set_mixnodes)
+	/// for the session will return immediately. Returns `true` if the session was active.
+	pub fn disable_session(&mut self, rel_session_index: RelSessionIndex) -> bool {
+		let session = &mut self.sessions[rel_session_index];
+		let was_full = matches!(session, SessionSlot::Full(_));
+		*session = SessionSlot::Disabled;
+		if was_full {
+			self.events |=
+				Events::RESERVED_PEERS_CHANGED | Events::NEXT_AUTHORED_PACKET_DEADLINE_CHANGED;
+		}
+		was_full
+	}
+
+	/// Returns the configuration in use for the specified session, or [`None`] if the session is
+	/// not active. This is either [`Config::mixnode_session`] or [`Config::non_mixnode_session`],
+	/// depending on whether the local node is a mixnode in the session.
+	pub fn session_config(&self, rel_session_index: RelSessionIndex) -> Option<&SessionConfig> {
+		let session = self.sessions[rel_session_index].as_option()?;
+		if session.topology.is_mixnode() {
+			Some(&self.config.mixnode_session)
+		} else {
+			self.config.non_mixnode_session.as_ref()
+		}
+	}
+
+	/// Change the number of gateway mixnodes to connect to in the specified session, when the
+	/// local node is not a mixnode in the session. Existing gateway mixnodes are kept where
+	/// possible. Note that [`Config::num_gateway_mixnodes`] is not changed; future sessions will
+	/// still use the configured number.
+	pub fn adjust_num_gateway_mixnodes(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+		num_gateway_mixnodes: u32,
+	) {
+		let Some(session) = self.sessions[rel_session_index].as_mut_option() else { return };
+		session.topology.resize_gateway_set(&mut rand::thread_rng(), num_gateway_mixnodes);
+		self.events |= Events::RESERVED_PEERS_CHANGED;
+	}
+
+	/// Returns the key-exchange public key of the specified mixnode in the specified session, or
+	/// [`None`] if the session is not active or the index is out of range.
+	pub fn session_mixnode_kx_public(
+		&self,
+		rel_session_index: RelSessionIndex,
+		index: MixnodeIndex,
+	) -> Option<KxPublic> {
+		self.sessions[rel_session_index]
+			.as_option()?
+			.topology
+			.kx_public_for_mixnode(index)
+			.copied()
+	}
+
+	/// Returns the specified mixnode in the specified session, including its
+	/// [`extra`](Mixnode::extra) data, or [`None`] if the session is not active or the index is
+	/// out of range.
+	pub fn session_mixnode(
+		&self,
+		rel_session_index: RelSessionIndex,
+		index: MixnodeIndex,
+	) -> Option<&Mixnode<X>> {
+		self.sessions[rel_session_index].as_option()?.topology.mixnode(index)
+	}
+
+	/// Returns the index of the mixnode with the given peer ID in the specified session, along
+	/// with the mixnode itself (including its [`extra`](Mixnode::extra) data). Returns [`None`]
+	/// if the session is not active or there is no such mixnode.
+	pub fn session_mixnode_for_peer_id(
+		&self,
+		rel_session_index: RelSessionIndex,
+		peer_id: &PeerId,
+	) -> Option<(MixnodeIndex, &Mixnode<X>)> {
+		self.sessions[rel_session_index].as_option()?.topology.mixnode_for_peer_id(peer_id)
+	}
+
 	/// Returns the key-exchange public key for the next session.
 	pub fn next_kx_public(&mut self) -> &KxPublic {
 		self.next_kx_pair
@@ -479,6 +880,37 @@ impl<X> Mixnet<X> {
 			.public()
 	}
 
+	/// Returns the maximum size of the data that can be passed to
+	/// [`post_request`](Self::post_request), given the configured
+	/// [`max_fragments_per_message`](Config::max_fragments_per_message). Each SURB requested
+	/// reduces this by [`SURB_SIZE`] bytes.
+	pub fn max_request_size_bytes(&self) -> usize {
+		FragmentAssembler::max_message_size_bytes(self.config.max_fragments_per_message)
+	}
+
+	/// Returns the key-exchange public keys we have for the previous, current, and next sessions,
+	/// along with the absolute session indices they correspond to. Sessions for which we have no
+	/// key pair (or which are disabled) are omitted, as is the next session if
+	/// [`next_kx_public`](Self::next_kx_public) has not been called yet.
+	///
+	/// Note that key pairs cannot be imported; the secret keys never leave this struct.
+	pub fn kx_publics(&self) -> Vec<(SessionIndex, KxPublic)> {
+		let current_index = self.session_status.current_index;
+		let mut kx_publics = Vec::new();
+		if let Some(prev_index) = current_index.checked_sub(1) {
+			if let Some(kx_public) = self.sessions.prev.kx_public() {
+				kx_publics.push((prev_index, *kx_public));
+			}
+		}
+		if let Some(kx_public) = self.sessions.current.kx_public() {
+			kx_publics.push((current_index, *kx_public));
+		}
+		if let Some(kx_pair) = &self.next_kx_pair {
+			kx_publics.push((current_index + 1, *kx_pair.public()));
+		}
+		kx_publics
+	}
+
 	/// Returns the mixnodes we should try to maintain connections to.
 	pub fn reserved_peers(&self) -> impl Iterator<Item = &Mixnode<X>> {
 		self.sessions.iter().flat_map(|session| session.topology.reserved_peers())
@@ -487,25 +919,74 @@ impl<X> Mixnet<X> {
 	/// Handle an incoming packet. If the packet completes a message, the message is returned.
 	/// Otherwise, [`None`] is returned.
 	pub fn handle_packet(&mut self, packet: &Packet) -> Option<Message> {
+		match self.handle_packet_inner(packet, None) {
+			Ok(message) => message,
+			Err(Timeout) => unreachable!("No deadline, so cannot time out"),
+		}
+	}
+
+	/// Like [`handle_packet`](Self::handle_packet), but gives up and returns [`Timeout`] if
+	/// `deadline` is reached. The deadline is checked before each key-exchange/decryption
+	/// attempt, so the packet may still be handled after the deadline if it was not reached
+	/// before the final attempt. If [`Timeout`] is returned, the packet has been ignored; in
+	/// particular it has not been added to the replay filter.
+	pub fn handle_packet_with_deadline(
+		&mut self,
+		packet: &Packet,
+		deadline: Instant,
+	) -> Result<Option<Message>, Timeout> {
+		self.handle_packet_inner(packet, Some(deadline))
+	}
+
+	/// Equivalent to [`handle_packet_with_deadline`](Self::handle_packet_with_deadline) with a
+	/// deadline of `timeout` from now.
+	pub fn handle_packet_with_timeout(
+		&mut self,
+		packet: &Packet,
+		timeout: Duration,
+	) -> Result<Option<Message>, Timeout> {
+		self.handle_packet_with_deadline(packet, Instant::now() + timeout)
+	}
+
+	fn handle_packet_inner(
+		&mut self,
+		packet: &Packet,
+		deadline: Option<Instant>,
+	) -> Result<Option<Message>, Timeout> {
+		#[cfg(any(test, feature = "fault-injection"))]
+		if self.simulate_packet_loss() {
+			return Ok(None)
+		}
+
 		let mut out = [0; PACKET_SIZE];
-		let res = self.sessions.enumerate_mut().find_map(|(rel_session_index, session)| {
+		let mut res = None;
+		for (rel_session_index, session) in self.sessions.enumerate_mut() {
+			if matches!(deadline, Some(deadline) if Instant::now() >= deadline) {
+				return Err(Timeout)
+			}
+
 			let kx_shared_secret = session.kx_pair.exchange(kx_public(packet));
 
 			let replay_tag = session.replay_filter.tag(&kx_shared_secret);
 			if session.replay_filter.contains(replay_tag) {
-				return Some(Err(Either::Left("Packet found in replay filter")))
+				res = Some(Err(Either::Left("Packet found in replay filter")));
+				break
 			}
 
-			match peel(&mut out, packet, &kx_shared_secret) {
+			res = match peel(&mut out, packet, &kx_shared_secret) {
 				// Bad MAC possibly means we used the wrong secret; try other session
 				Err(PeelErr::Mac) => None,
 				// Any other error means the packet is bad; just discard it
 				Err(err) => Some(Err(Either::Right(err))),
-				Ok(action) => Some(Ok((action, rel_session_index, session, replay_tag))),
+				Ok(action) => Some(Ok((action, rel_session_index, replay_tag))),
+			};
+
+			if res.is_some() {
+				break
 			}
-		});
+		}
 
-		let (action, rel_session_index, session, replay_tag) = match res {
+		let (action, rel_session_index, replay_tag) = match res {
 			None => {
 				// This will usually get hit quite a bit on session changeover after we discard the
 				// keys for the previous session. It may get hit just before a new session if other
@@ -514,15 +995,59 @@ impl<X> Mixnet<X> {
 					target: self.config.log_target,
 					"Failed to peel packet; either bad MAC or unknown secret"
 				);
-				return None
+				return Ok(None)
 			},
 			Some(Err(err)) => {
 				debug!(target: self.config.log_target, "Failed to peel packet: {err}");
-				return None
+				return Ok(None)
 			},
 			Some(Ok(x)) => x,
 		};
 
+		Ok(self.handle_peeled_packet(&mut out, action, rel_session_index, Some(replay_tag)))
+	}
+
+	/// Handle a packet as if it had been peeled using the key-exchange keys for the specified
+	/// session, with the given resulting `action`. `payload` is copied to the start of the peeled
+	/// packet buffer (which is otherwise zeroed), and so should be the packet to forward for
+	/// [`Action::ForwardTo`], the payload data for [`Action::DeliverRequest`], or the encrypted
+	/// payload for [`Action::DeliverReply`]. The replay filter is neither checked nor updated.
+	/// This is intended for testing purposes only.
+	#[cfg(any(test, feature = "test-utils"))]
+	pub fn inject_test_packet(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+		action: Action,
+		payload: &[u8],
+	) -> Option<Message> {
+		let mut out = [0; PACKET_SIZE];
+		let len = min(payload.len(), PACKET_SIZE);
+		out[..len].copy_from_slice(&payload[..len]);
+		self.handle_peeled_packet(&mut out, action, rel_session_index, None)
+	}
+
+	/// Handle a reply as if a packet with a [`Action::DeliverReply`] action had been peeled. This
+	/// bypasses the Sphinx header processing but not the SURB keystore lookup or payload
+	/// decryption, so `payload` must be the complete encrypted payload of a reply packet, as
+	/// built using the SURB with ID `surb_id`. The current session must be active. This is
+	/// intended for testing purposes only.
+	#[cfg(any(test, feature = "test-utils"))]
+	pub fn inject_surb_reply(&mut self, surb_id: SurbId, payload: &[u8]) -> Option<Message> {
+		self.inject_test_packet(RelSessionIndex::Current, Action::DeliverReply { surb_id }, payload)
+	}
+
+	/// Handle a packet that has been successfully peeled (or injected, for testing). `out` should
+	/// contain the peeled packet. `replay_tag` should be inserted into the replay filter for the
+	/// session if the packet is accepted.
+	fn handle_peeled_packet(
+		&mut self,
+		out: &mut Packet,
+		action: Action,
+		rel_session_index: RelSessionIndex,
+		replay_tag: Option<ReplayTag>,
+	) -> Option<Message> {
+		let session = self.sessions[rel_session_index].as_mut_option()?;
+
 		match action {
 			Action::ForwardTo { target, delay } => {
 				if !session.topology.is_mixnode() {
@@ -530,6 +1055,11 @@ impl<X> Mixnet<X> {
 						"Received packet to forward despite not being a mixnode in the session; discarding");
 					return None
 				}
+				if !self.session_status.phase.gen_forward_packets(rel_session_index) {
+					debug!(target: self.config.log_target,
+						"Received packet to forward in a session that is no longer needed; discarding");
+					return None
+				}
 
 				if !self.forward_packet_queue.has_space() {
 					debug!(target: self.config.log_target, "Dropped forward packet; forward queue full");
@@ -538,13 +1068,15 @@ impl<X> Mixnet<X> {
 
 				// After the is_mixnode check to avoid inserting anything into the replay filters
 				// for sessions where we are not a mixnode
-				session.replay_filter.insert(replay_tag);
+				if let Some(replay_tag) = replay_tag {
+					session.replay_filter.insert(replay_tag);
+				}
 
 				match session.topology.target_to_peer_id(&target) {
 					Ok(peer_id) => {
 						let deadline =
 							Instant::now() + delay.to_duration(self.config.mean_forwarding_delay);
-						let packet = AddressedPacket { peer_id, packet: out.into() };
+						let packet = AddressedPacket { peer_id, packet: (*out).into() };
 						if self.forward_packet_queue.insert(deadline, packet) {
 							self.events |= Events::NEXT_FORWARD_PACKET_DEADLINE_CHANGED;
 						}
@@ -568,7 +1100,9 @@ impl<X> Mixnet<X> {
 
 				// After the is_mixnode check to avoid inserting anything into the replay filters
 				// for sessions where we are not a mixnode
-				session.replay_filter.insert(replay_tag);
+				if let Some(replay_tag) = replay_tag {
+					session.replay_filter.insert(replay_tag);
+				}
 
 				// Add to fragment assembler and return any completed message
 				self.fragment_assembler.insert(payload_data, self.config.log_target).map(
@@ -614,6 +1148,7 @@ impl<X> Mixnet<X> {
 							debug!(target: self.config.log_target,
 								"Reply message included SURBs; discarding them");
 						}
+						self.retry_tracker.remove(&request_id);
 						Message::Reply(ReplyMessage { request_id, data: message.data })
 					},
 				)
@@ -636,16 +1171,58 @@ impl<X> Mixnet<X> {
 		self.forward_packet_queue.pop()
 	}
 
-	/// Returns the delay after which [`pop_next_authored_packet`](Self::pop_next_authored_packet)
-	/// should be called. [`None`] means an infinite delay.
-	pub fn next_authored_packet_delay(&self) -> Option<Duration> {
-		// Determine the mean period
+	/// Change [`Config::mean_forwarding_delay`]. This should really be the same for all nodes, so
+	/// it should only be changed in coordination with other nodes. The deadlines of packets
+	/// already in the forward packet queue are not recalculated; these packets will be forwarded
+	/// according to the old mean delay.
+	pub fn update_config_mean_forwarding_delay(&mut self, new_delay: Duration) {
+		if self.config.mean_forwarding_delay == new_delay {
+			return
+		}
+		if self.forward_packet_queue.next_deadline().is_some() {
+			warn!(
+				target: self.config.log_target,
+				"Mean forwarding delay changed from {:?} to {new_delay:?}; \
+				packets already in the forward queue will use the old delay",
+				self.config.mean_forwarding_delay
+			);
+		}
+		self.config.mean_forwarding_delay = new_delay;
+		self.events |= Events::NEXT_FORWARD_PACKET_DEADLINE_CHANGED;
+	}
+
+	/// Change [`Config::loop_cover_proportion`]. `proportion` must be between 0 and 1.
+	pub fn set_loop_cover_proportion(&mut self, proportion: f64) -> Result<(), ConfigErr> {
+		if !(0.0..=1.0).contains(&proportion) {
+			return Err(ConfigErr::BadLoopCoverProportion(proportion))
+		}
+		self.config.loop_cover_proportion = proportion;
+		self.events |= Events::NEXT_AUTHORED_PACKET_DEADLINE_CHANGED;
+		Ok(())
+	}
+
+	/// Postpone the deadlines of all packets in the forward packet queue destined for `peer_id` by
+	/// `delta`. This can be used to avoid dropping packets when a peer is temporarily unreachable.
+	/// Returns the number of packets that were rescheduled.
+	pub fn reschedule_forward_packets(&mut self, peer_id: &PeerId, delta: Duration) -> usize {
+		let prev_deadline = self.forward_packet_queue.next_deadline();
+		let num_rescheduled = self.forward_packet_queue.reschedule(peer_id, delta);
+		if self.forward_packet_queue.next_deadline() != prev_deadline {
+			self.events |= Events::NEXT_FORWARD_PACKET_DEADLINE_CHANGED;
+		}
+		num_rescheduled
+	}
+
+	/// Returns the mean period in seconds between
+	/// [`pop_next_authored_packet`](Self::pop_next_authored_packet) calls, or [`None`] if no
+	/// sessions are active.
+	fn mean_authored_packet_period_secs(&self) -> Option<f64> {
 		let means: ArrayVec<_, 2> = self
 			.sessions
 			.iter()
 			.map(|session| session.mean_authored_packet_period.as_secs_f64())
 			.collect();
-		let mean = match means.into_inner() {
+		Some(match means.into_inner() {
 			// Both sessions active. Send at half rate in each. Note that pop_next_authored_packet
 			// will choose between the sessions randomly based on their rates.
 			Ok(means) => (2.0 * means[0] * means[1]) / (means[0] + means[1]),
@@ -659,20 +1236,124 @@ impl<X> Mixnet<X> {
 					mean
 				}
 			},
-		};
+		})
+	}
 
+	/// Returns the delay after which [`pop_next_authored_packet`](Self::pop_next_authored_packet)
+	/// should be called. [`None`] means an infinite delay.
+	pub fn next_authored_packet_delay(&self) -> Option<Duration> {
+		let mean = self.mean_authored_packet_period_secs()?;
 		let delay: f64 = rand::thread_rng().sample(rand_distr::Exp1);
 		// Cap at 10x the mean; this is about the 99.995th percentile. This avoids potential panics
 		// in from_secs_f64() due to overflow.
 		Some(Duration::from_secs_f64(delay.min(10.0) * mean))
 	}
 
+	/// Returns the expected rate, in packets per second, at which packets (real and cover) are
+	/// authored, across all active sessions. Returns 0 if no sessions are active. Note that the
+	/// actual rate may be lower if cover packet generation fails or is disabled.
+	pub fn authored_packet_throughput_estimate(&self) -> f64 {
+		self.mean_authored_packet_period_secs().map_or(0.0, |mean| 1.0 / mean)
+	}
+
+	/// Returns an estimate of the rate, in packets per second, at which packets are currently
+	/// being forwarded. This is derived from the number of packets in the forward packet queue and
+	/// the mean forwarding delay (by Little's law), so it is only meaningful when the rate of
+	/// arrival is fairly steady.
+	pub fn forward_packet_throughput_estimate(&self) -> f64 {
+		let mean_delay = self.config.mean_forwarding_delay.as_secs_f64();
+		if mean_delay == 0.0 {
+			return 0.0
+		}
+		(self.forward_packet_queue.len() as f64) / mean_delay
+	}
+
 	/// Either generate and return a cover packet or pop and return the packet at the head of one
 	/// of the authored packet queues. May return [`None`] if cover packets are disabled, we fail
 	/// to generate a cover packet, or there are no active sessions (though in the no active
 	/// sessions case [`next_authored_packet_delay`](Self::next_authored_packet_delay) should
 	/// return [`None`] and so this function should not really be called).
 	pub fn pop_next_authored_packet(&mut self, ns: &dyn NetworkStatus) -> Option<AddressedPacket> {
+		self.authored_queue_tagged_pop(ns).map(|(packet, _tag)| packet)
+	}
+
+	/// Like [`pop_next_authored_packet`](Self::pop_next_authored_packet), but also returns the
+	/// tag the packet was posted with (see [`PostOptions::tag`]). The tag is 0 for cover packets
+	/// and packets posted without a tag.
+	pub fn authored_queue_tagged_pop(
+		&mut self,
+		ns: &dyn NetworkStatus,
+	) -> Option<(AddressedPacket, u64)> {
+		let packet = self.gen_or_pop_authored_packet(ns, false);
+		#[cfg(any(test, feature = "fault-injection"))]
+		if packet.is_some() && self.simulate_packet_loss() {
+			return None
+		}
+		packet
+	}
+
+	/// Equivalent to calling [`pop_next_authored_packet`](Self::pop_next_authored_packet) `max`
+	/// times and collecting the returned packets. Each call corresponds to one event of the
+	/// authored packet Poisson process, so this should only be used when `max` events have
+	/// occurred, for example when a timer fired late or the caller prefers to send packets in
+	/// bursts. The returned vector may contain fewer than `max` packets.
+	pub fn authored_queue_pop_batch(
+		&mut self,
+		max: usize,
+		ns: &dyn NetworkStatus,
+	) -> Vec<AddressedPacket> {
+		(0..max).filter_map(|_| self.pop_next_authored_packet(ns)).collect()
+	}
+
+	/// Generate a cover packet of the given kind for the specified session, independently of the
+	/// normal authored packet schedule. This can be used to eg pad bandwidth during idle periods.
+	/// Returns [`None`] if the session is not active or a packet could not be generated (eg
+	/// because we are not connected to any gateway mixnodes). The authored packet schedule is
+	/// unaffected, so no events are raised.
+	///
+	/// Note that [`Config::gen_cover_packets`] is ignored, and that packets generated by this
+	/// function do not count towards [`Config::min_loop_cover_ratio`].
+	pub fn gen_cover_packet(
+		&self,
+		rel_session_index: RelSessionIndex,
+		kind: CoverKind,
+		ns: &dyn NetworkStatus,
+	) -> Option<AddressedPacket> {
+		let session = self.sessions[rel_session_index].as_option()?;
+		let mut rng = rand::thread_rng();
+		match gen_cover_packet(&mut rng, &session.topology, ns, kind, self.config.num_hops, None) {
+			Ok(packet) => Some(packet),
+			Err(err) => {
+				debug!(target: self.config.log_target, "Failed to generate cover packet: {err}");
+				None
+			},
+		}
+	}
+
+	/// Like [`pop_next_authored_packet`](Self::pop_next_authored_packet), but instead of popping
+	/// the packet at the head of an authored packet queue, pops the first packet in the queue
+	/// whose destination is currently connected (according to `ns`). This avoids packets for
+	/// other peers being held up behind packets for a disconnected peer. Note that this may
+	/// reorder the fragments of a message.
+	pub fn authored_queue_pop_addressed_to_connected_peers_only(
+		&mut self,
+		ns: &dyn NetworkStatus,
+	) -> Option<AddressedPacket> {
+		let packet = self.gen_or_pop_authored_packet(ns, true);
+		#[cfg(any(test, feature = "fault-injection"))]
+		if packet.is_some() && self.simulate_packet_loss() {
+			return None
+		}
+		packet.map(|(packet, _tag)| packet)
+	}
+
+	/// If `connected_only` is `true`, packets in the authored packet queues for disconnected
+	/// peers are skipped over. The returned packet is paired with its tag.
+	fn gen_or_pop_authored_packet(
+		&mut self,
+		ns: &dyn NetworkStatus,
+		connected_only: bool,
+	) -> Option<(AddressedPacket, u64)> {
 		// This function should be called according to a Poisson process. Randomly choosing between
 		// sessions and cover kinds here is equivalent to there being multiple independent Poisson
 		// processes; see https://www.randomservices.org/random/poisson/Splitting.html
@@ -706,18 +1387,31 @@ impl<X> Mixnet<X> {
 
 		self.events |= Events::NEXT_AUTHORED_PACKET_DEADLINE_CHANGED;
 
-		// Choose randomly between drop and loop cover packet
-		let cover_kind = if rng.gen_bool(self.config.loop_cover_proportion) {
+		if session.authored_packet_queue.is_paused() {
+			return None
+		}
+
+		// Choose randomly between drop and loop cover packet, unless too few loop cover packets
+		// have been sent recently
+		let force_loop =
+			match (self.config.min_loop_cover_ratio, self.loop_cover_window.loop_proportion()) {
+				(Some(min_ratio), Some(proportion)) => proportion < min_ratio,
+				_ => false,
+			};
+		let cover_kind = if force_loop || rng.gen_bool(self.config.loop_cover_proportion) {
 			CoverKind::Loop
 		} else {
 			CoverKind::Drop
 		};
+		self.loop_cover_window.push(&cover_kind);
 
 		// Maybe replace drop cover packet with request or reply packet from queue
 		if (cover_kind == CoverKind::Drop) &&
 			self.session_status.phase.allow_requests_and_replies(rel_session_index)
 		{
-			let (packet, space) = session.authored_packet_queue.pop();
+			let (packet, space) = session
+				.authored_packet_queue
+				.pop_first_tagged(|packet| !connected_only || ns.is_connected(&packet.peer_id));
 			if space {
 				self.events |= Events::SPACE_IN_AUTHORED_PACKET_QUEUE;
 			}
@@ -731,8 +1425,15 @@ impl<X> Mixnet<X> {
 		}
 
 		// Generate cover packet
-		match gen_cover_packet(&mut rng, &session.topology, ns, cover_kind, self.config.num_hops) {
-			Ok(packet) => Some(packet),
+		match gen_cover_packet(
+			&mut rng,
+			&session.topology,
+			ns,
+			cover_kind,
+			self.config.num_hops,
+			None,
+		) {
+			Ok(packet) => Some((packet, 0)),
 			Err(err) => {
 				if (self.session_status.phase == SessionPhase::CoverToCurrent) &&
 					(rel_session_index == RelSessionIndex::Current) &&
@@ -748,9 +1449,28 @@ impl<X> Mixnet<X> {
 		}
 	}
 
+	/// Returns `true` if a packet should be dropped according to
+	/// [`Config::packet_drop_probability`].
+	#[cfg(any(test, feature = "fault-injection"))]
+	fn simulate_packet_loss(&self) -> bool {
+		let drop = (self.config.packet_drop_probability > 0.0) &&
+			rand::thread_rng().gen_bool(self.config.packet_drop_probability);
+		if drop {
+			trace!(target: self.config.log_target, "Dropping packet to simulate packet loss");
+		}
+		drop
+	}
+
 	/// Post a request message. If `destination_index` is [`None`], a destination mixnode is chosen
 	/// at random and (on success) its index is written back to `destination_index`. The message is
 	/// split into fragments and each fragment is sent over a different path to the destination.
+	///
+	/// `message_id` is chosen by the caller, and may be used to correlate replies with requests
+	/// (it is included in [`ReplyMessage::request_id`]). Note that the destination reassembles
+	/// fragments by message ID, so if the ID is derived from some other data (eg a transaction
+	/// hash) rather than randomly generated, care must be taken to never use the same ID for two
+	/// different messages that might be in flight at the same time. Reusing an ID also allows
+	/// observers of both messages at the destination to link them.
 	pub fn post_request(
 		&mut self,
 		session_index: SessionIndex,
@@ -759,6 +1479,97 @@ impl<X> Mixnet<X> {
 		data: Scattered<u8>,
 		num_surbs: usize,
 		ns: &dyn NetworkStatus,
+	) -> Result<RequestMetrics, PostErr> {
+		self.post_request_with_options(
+			session_index,
+			destination_index,
+			message_id,
+			data,
+			num_surbs,
+			ns,
+			&PostOptions::default(),
+		)
+	}
+
+	/// Like [`post_request`](Self::post_request), but with additional options.
+	#[allow(clippy::too_many_arguments)]
+	pub fn post_request_with_options(
+		&mut self,
+		session_index: SessionIndex,
+		destination_index: &mut Option<MixnodeIndex>,
+		message_id: &MessageId,
+		data: Scattered<u8>,
+		num_surbs: usize,
+		ns: &dyn NetworkStatus,
+		options: &PostOptions,
+	) -> Result<RequestMetrics, PostErr> {
+		self.post_request_inner(
+			session_index,
+			destination_index,
+			message_id,
+			data,
+			num_surbs,
+			ns,
+			options,
+			None,
+		)
+	}
+
+	/// Reserve space for `count` packets in the authored packet queue for the specified session.
+	/// The space can only be used by requests posted with
+	/// [`post_request_with_reservation`](Self::post_request_with_reservation) and the returned
+	/// reservation. This allows a caller to guarantee that a series of requests will fit in the
+	/// queue before posting any of them. Any unused space is released when the reservation is
+	/// dropped; note that this does not raise [`Events::SPACE_IN_AUTHORED_PACKET_QUEUE`].
+	pub fn reserve_authored_queue_space(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+		count: usize,
+	) -> Result<Reservation, PostErr> {
+		let session_index = rel_session_index + self.session_status.current_index;
+		let session = post_session(&mut self.sessions, self.session_status, session_index)?;
+		Ok(session.authored_packet_queue.reserve(count)?)
+	}
+
+	/// Like [`post_request`](Self::post_request), but uses space reserved by
+	/// [`reserve_authored_queue_space`](Self::reserve_authored_queue_space). If the request
+	/// needs more packets than remain in the reservation, the rest must fit in the queue as
+	/// normal. Space is only taken from the reservation if posting succeeds. Fails with
+	/// [`PostErr::BadReservation`] if the reservation was made for a different session.
+	#[allow(clippy::too_many_arguments)]
+	pub fn post_request_with_reservation(
+		&mut self,
+		session_index: SessionIndex,
+		reservation: &mut Reservation,
+		destination_index: &mut Option<MixnodeIndex>,
+		message_id: &MessageId,
+		data: Scattered<u8>,
+		num_surbs: usize,
+		ns: &dyn NetworkStatus,
+	) -> Result<RequestMetrics, PostErr> {
+		self.post_request_inner(
+			session_index,
+			destination_index,
+			message_id,
+			data,
+			num_surbs,
+			ns,
+			&PostOptions::default(),
+			Some(reservation),
+		)
+	}
+
+	#[allow(clippy::too_many_arguments)]
+	fn post_request_inner(
+		&mut self,
+		session_index: SessionIndex,
+		destination_index: &mut Option<MixnodeIndex>,
+		message_id: &MessageId,
+		data: Scattered<u8>,
+		num_surbs: usize,
+		ns: &dyn NetworkStatus,
+		options: &PostOptions,
+		reservation: Option<&mut Reservation>,
 	) -> Result<RequestMetrics, PostErr> {
 		// Split the message into fragments
 		let fragment_blueprints = match fragment_blueprints(message_id, data, num_surbs) {
@@ -770,17 +1581,44 @@ impl<X> Mixnet<X> {
 
 		// Grab the session and check there's room in the queue
 		let session = post_session(&mut self.sessions, self.session_status, session_index)?;
-		session.authored_packet_queue.check_space(fragment_blueprints.len())?;
-
-		// Generate the packets and push them into the queue
+		let reservation = match reservation {
+			Some(reservation) if !session.authored_packet_queue.owns_reservation(reservation) =>
+				return Err(PostErr::BadReservation),
+			reservation => reservation,
+		};
+		let eviction_plan = session.authored_packet_queue.plan_eviction(
+			fragment_blueprints.len(),
+			reservation.as_deref(),
+			&*self.authored_queue_eviction_policy,
+		)?;
+
+		// Generate the packets. Nothing is evicted or pushed until all of the packets have been
+		// generated successfully.
 		let mut rng = rand::thread_rng();
 		let request_builder =
 			RequestBuilder::new(&mut rng, &session.topology, ns, *destination_index)?;
+		// Where possible, send each fragment via a different first hop, for path diversity. If
+		// there are not enough candidates, the remaining fragments get random first hops as
+		// normal. This is only done when the local node is a mixnode; otherwise the first hop
+		// must be a connected gateway mixnode, and there are typically only a few of these.
+		let num_fragments = fragment_blueprints.len();
+		let mut first_indices = if (num_fragments > 1) && session.topology.is_mixnode() {
+			session.topology.first_hop_candidates_excluding(
+				&mut rng,
+				num_fragments,
+				Some(request_builder.destination_index()),
+			)
+		} else {
+			Vec::new()
+		}
+		.into_iter();
 		let mut request_hops = 0;
 		let mut request_forwarding_delay = Delay::zero();
 		let mut reply_hops = 0;
 		let mut reply_forwarding_delay = Delay::zero();
+		let mut packets = Vec::with_capacity(num_fragments);
 		for fragment_blueprint in fragment_blueprints {
+			let fragment_index = fragment_blueprint.index();
 			let (packet, metrics) = request_builder.build_packet(
 				&mut rng,
 				|fragment, rng| {
@@ -798,12 +1636,30 @@ impl<X> Mixnet<X> {
 					Ok(())
 				},
 				self.config.num_hops,
+				first_indices.next(),
 			)?;
-			session.authored_packet_queue.push(packet);
+			packets.push((packet, fragment_index));
 			request_hops = max(request_hops, metrics.num_hops);
 			request_forwarding_delay = max(request_forwarding_delay, metrics.forwarding_delay);
 		}
 
+		// Make room for the packets and push them into the queue
+		session.authored_packet_queue.evict(eviction_plan);
+		for (packet, fragment_index) in packets {
+			session.authored_packet_queue.push_tagged(
+				packet,
+				message_id,
+				fragment_index,
+				options.tag,
+			);
+		}
+
+		// The packets are now in the queue; release the reserved slots they used
+		if let Some(reservation) = reservation {
+			let owned = session.authored_packet_queue.use_reservation(reservation, num_fragments);
+			debug_assert!(owned);
+		}
+
 		// Calculate metrics
 		let metrics = RequestMetrics {
 			num_hops: request_hops + reply_hops,
@@ -820,8 +1676,100 @@ impl<X> Mixnet<X> {
 		Ok(metrics)
 	}
 
+	/// Like [`post_request`](Self::post_request), but remembers the request so that it can be
+	/// re-posted by [`notify_request_timeout`](Self::notify_request_timeout) if no reply is
+	/// received within `retry_budget`. The request is forgotten once a reply is received, after
+	/// [`Config::max_request_retries`] retries, if re-posting fails, when the session ends, or
+	/// when [`cancel_request_retries`](Self::cancel_request_retries) is called. At most
+	/// [`Config::max_retried_requests`] requests are remembered.
+	///
+	/// Re-posted requests use the same message ID and destination, but fresh SURBs.
+	#[allow(clippy::too_many_arguments)]
+	pub fn post_request_with_reliability(
+		&mut self,
+		session_index: SessionIndex,
+		destination_index: &mut Option<MixnodeIndex>,
+		message_id: &MessageId,
+		data: Scattered<u8>,
+		num_surbs: usize,
+		ns: &dyn NetworkStatus,
+		retry_budget: Duration,
+	) -> Result<RequestMetrics, PostErr> {
+		let owned_data = data.to_vec();
+		let metrics = self.post_request(
+			session_index,
+			destination_index,
+			message_id,
+			data,
+			num_surbs,
+			ns,
+		)?;
+		let destination_index = destination_index.expect("Set by successful post_request()");
+		self.retry_tracker.insert(
+			*message_id,
+			session_index,
+			destination_index,
+			owned_data,
+			num_surbs,
+			retry_budget,
+		);
+		Ok(metrics)
+	}
+
+	/// Should be called when a request posted with
+	/// [`post_request_with_reliability`](Self::post_request_with_reliability) may have timed out.
+	/// If no reply has been received and the retry budget has elapsed since the request was last
+	/// posted, the request is re-posted. Returns `true` if the request was re-posted.
+	pub fn notify_request_timeout(
+		&mut self,
+		message_id: &MessageId,
+		ns: &dyn NetworkStatus,
+	) -> bool {
+		let Some(request) = self.retry_tracker.timed_out(
+			message_id,
+			Instant::now(),
+			self.config.max_request_retries,
+		) else {
+			return false
+		};
+		let session_index = request.session_index;
+		let mut destination_index = Some(request.destination_index);
+		let data = request.data.clone();
+		let num_surbs = request.num_surbs;
+		match self.post_request(
+			session_index,
+			&mut destination_index,
+			message_id,
+			data.as_slice().into(),
+			num_surbs,
+			ns,
+		) {
+			Ok(_) => true,
+			Err(err) => {
+				debug!(target: self.config.log_target,
+					"Failed to re-post request {message_id:x?}: {err}");
+				self.retry_tracker.remove(message_id);
+				false
+			},
+		}
+	}
+
+	/// Stop retrying the request with the given message ID, which should have been posted with
+	/// [`post_request_with_reliability`](Self::post_request_with_reliability). Any packets already
+	/// in the authored packet queue are left there. Returns `true` if the request was being
+	/// retried.
+	pub fn cancel_request_retries(&mut self, message_id: &MessageId) -> bool {
+		self.retry_tracker.remove(message_id)
+	}
+
 	/// Post a reply message using SURBs. The session index must match the session the SURBs were
 	/// generated for. SURBs are removed from `surbs` on use.
+	///
+	/// Each fragment of the reply consumes one SURB. If there are not enough SURBs,
+	/// [`PostErr::TooManyFragments`] is returned. Note that it is not possible to ask the requester
+	/// for more SURBs; the requester is anonymous, so SURBs are the only way of reaching it.
+	/// Protocols that need to send large replies should have the requester attach enough SURBs,
+	/// or split the exchange into multiple requests.
 	pub fn post_reply(
 		&mut self,
 		surbs: &mut Vec<Surb>,
@@ -840,9 +1788,15 @@ impl<X> Mixnet<X> {
 
 		// Grab the session and check there's room in the queue
 		let session = post_session(&mut self.sessions, self.session_status, session_index)?;
-		session.authored_packet_queue.check_space(fragment_blueprints.len())?;
-
-		// Generate the packets and push them into the queue
+		let eviction_plan = session.authored_packet_queue.plan_eviction(
+			fragment_blueprints.len(),
+			None,
+			&*self.authored_queue_eviction_policy,
+		)?;
+
+		// Generate the packets. Nothing is evicted or pushed until all of the packets have been
+		// generated successfully.
+		let mut packets = Vec::with_capacity(fragment_blueprints.len());
 		for fragment_blueprint in fragment_blueprints {
 			let mut packet = default_boxed_array();
 			fragment_blueprint.write_except_surbs(mut_payload_data(&mut packet));
@@ -852,16 +1806,667 @@ impl<X> Mixnet<X> {
 			)
 			.ok_or(PostErr::BadSurb)?;
 			let peer_id = session.topology.mixnode_index_to_peer_id(mixnode_index)?;
-			session.authored_packet_queue.push(AddressedPacket { peer_id, packet });
+			packets.push((AddressedPacket { peer_id, packet }, fragment_blueprint.index()));
+		}
+
+		// Make room for the packets and push them into the queue
+		session.authored_packet_queue.evict(eviction_plan);
+		for (packet, fragment_index) in packets {
+			session.authored_packet_queue.push_with_message_id(packet, message_id, fragment_index);
+		}
+
+		Ok(())
+	}
+
+	/// Returns references to all packets in the authored packet queue for the specified session,
+	/// from head to tail, without removing them. Returns an empty vector if the session is not
+	/// active. This is intended for debugging and monitoring.
+	pub fn authored_queue_peek_all(
+		&self,
+		rel_session_index: RelSessionIndex,
+	) -> Vec<&AddressedPacket> {
+		self.sessions[rel_session_index]
+			.as_option()
+			.map_or_else(Vec::new, |session| session.authored_packet_queue.iter().collect())
+	}
+
+	/// Returns `true` if there are real (request or reply) packets waiting in the authored packet
+	/// queue for the specified session. Returns `false` if the session is not active. Cover
+	/// packets are normally generated on demand and not queued, but see
+	/// [`authored_queue_requeue_as_drop_cover`](Self::authored_queue_requeue_as_drop_cover).
+	pub fn authored_queue_contains_real_packets(&self, rel_session_index: RelSessionIndex) -> bool {
+		matches!(self.sessions[rel_session_index].as_option(),
+			Some(session) if session.authored_packet_queue.contains_real_packets())
+	}
+
+	/// Replace all real packets in the authored packet queue for the specified session with drop
+	/// cover packets, sent to the same first hop. This can be used to abandon queued messages
+	/// without revealing, through a change in traffic, that they were ever queued. Real packets
+	/// for which a replacement cover packet cannot be generated are simply dropped.
+	pub fn authored_queue_requeue_as_drop_cover(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+		ns: &dyn NetworkStatus,
+	) {
+		let Some(session) = self.sessions[rel_session_index].as_mut_option() else { return };
+		let mut rng = rand::thread_rng();
+		let topology = &session.topology;
+		let num_hops = self.config.num_hops;
+		let space = session.authored_packet_queue.replace_real_with_cover(|packet| {
+			gen_drop_cover_packet_like(&mut rng, topology, ns, num_hops, packet)
+		});
+		if space {
+			self.events |= Events::SPACE_IN_AUTHORED_PACKET_QUEUE;
+		}
+	}
+
+	/// Remove all packets that have been in the authored packet queue for the specified session
+	/// for longer than `max_age`. Such packets are likely to be useless, for example because the
+	/// requests they belong to will have timed out. A warning is logged for each removed packet.
+	/// Returns the number of packets removed, or 0 if the session is not active.
+	pub fn evict_stale_authored_packets(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+		max_age: Duration,
+	) -> usize {
+		let Some(session) = self.sessions[rel_session_index].as_mut_option() else { return 0 };
+		let Some(cutoff) = Instant::now().checked_sub(max_age) else { return 0 };
+		let removed = session.authored_packet_queue.remove_enqueued_before(cutoff);
+		for peer_id in &removed {
+			warn!(
+				target: self.config.log_target,
+				"Evicting stale authored packet destined for peer {peer_id:x?}"
+			);
+		}
+		if !removed.is_empty() {
+			self.events |= Events::SPACE_IN_AUTHORED_PACKET_QUEUE;
+		}
+		removed.len()
+	}
+
+	/// Move all packets belonging to the message with ID `message_id` to the front of the
+	/// authored packet queue for the specified session, so that they are sent before any other
+	/// queued packets. The packets are ordered by fragment index. Returns the number of packets
+	/// moved, or 0 if the session is not active.
+	///
+	/// Only packets queued by [`post_request`](Self::post_request) or
+	/// [`post_reply`](Self::post_reply) in this process are recognised as belonging to a message.
+	pub fn authored_queue_move_to_front(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+		message_id: &MessageId,
+	) -> usize {
+		self.sessions[rel_session_index].as_mut_option().map_or(0, |session| {
+			session.authored_packet_queue.move_message_to_front(message_id)
+		})
+	}
+
+	/// Drop all packets destined for `peer_id` from the authored packet queue for the specified
+	/// session, for example because the peer has been banned. A warning including `reason` is
+	/// logged for each dropped packet. Returns the number of packets dropped, or 0 if the session
+	/// is not active. Note that dropping packets will typically leave messages incomplete.
+	pub fn authored_queue_drop_by_destination(
+		&mut self,
+		peer_id: &PeerId,
+		rel_session_index: RelSessionIndex,
+		reason: DropReason,
+	) -> usize {
+		let Some(session) = self.sessions[rel_session_index].as_mut_option() else { return 0 };
+		let num_dropped = session.authored_packet_queue.remove_for_peer(peer_id);
+		for _ in 0..num_dropped {
+			warn!(
+				target: self.config.log_target,
+				"Dropping authored packet destined for peer {peer_id:x?}: {reason}"
+			);
+		}
+		if num_dropped > 0 {
+			self.events |= Events::SPACE_IN_AUTHORED_PACKET_QUEUE;
+		}
+		num_dropped
+	}
+
+	/// Remove duplicate packets from the authored packet queue for the specified session. Packets
+	/// are considered duplicates if they carry the same fragment of the same message, which can
+	/// happen if a message is accidentally posted twice. The first such packet in the queue is
+	/// kept. Returns the number of packets removed, or 0 if the session is not active.
+	///
+	/// Only packets queued by [`post_request`](Self::post_request) or
+	/// [`post_reply`](Self::post_reply) in this process are recognised as belonging to a message.
+	pub fn authored_queue_deduplicate(&mut self, rel_session_index: RelSessionIndex) -> usize {
+		let Some(session) = self.sessions[rel_session_index].as_mut_option() else { return 0 };
+		let num_removed = session.authored_packet_queue.deduplicate();
+		if num_removed > 0 {
+			self.events |= Events::SPACE_IN_AUTHORED_PACKET_QUEUE;
+		}
+		num_removed
+	}
+
+	/// Returns `true` if the authored packet queue for the specified session contains any packets
+	/// belonging to the message with ID `message_id`. This can be used to avoid posting the same
+	/// message twice. Returns `false` if the session is not active.
+	///
+	/// Only packets queued by [`post_request`](Self::post_request) or
+	/// [`post_reply`](Self::post_reply) in this process are recognised as belonging to a message;
+	/// the message ID cannot be recovered from the (encrypted) packets themselves.
+	pub fn authored_queue_contains_message(
+		&self,
+		rel_session_index: RelSessionIndex,
+		message_id: &MessageId,
+	) -> bool {
+		matches!(self.sessions[rel_session_index].as_option(),
+			Some(session) if session.authored_packet_queue.contains_message(message_id))
+	}
+
+	/// Replace the packets belonging to the message with ID `message_id` in the authored packet
+	/// queue for the specified session with drop cover packets, sent to the same first hop. This
+	/// can be used to abandon a message without revealing, through a change in traffic, that it
+	/// was ever queued. Packets for which a replacement cover packet cannot be generated are
+	/// simply dropped. Returns the number of packets replaced.
+	///
+	/// Only packets queued by [`post_request`](Self::post_request) or
+	/// [`post_reply`](Self::post_reply) in this process are recognised as belonging to a message.
+	pub fn authored_queue_convert_real_to_drop_cover(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+		message_id: &MessageId,
+		ns: &dyn NetworkStatus,
+	) -> usize {
+		let Some(session) = self.sessions[rel_session_index].as_mut_option() else { return 0 };
+		let mut rng = rand::thread_rng();
+		let topology = &session.topology;
+		let num_hops = self.config.num_hops;
+		let (num_replaced, space) =
+			session.authored_packet_queue.replace_message_with_cover(message_id, |packet| {
+				gen_drop_cover_packet_like(&mut rng, topology, ns, num_hops, packet)
+			});
+		if space {
+			self.events |= Events::SPACE_IN_AUTHORED_PACKET_QUEUE;
 		}
+		num_replaced
+	}
 
+	/// Check the integrity of the authored packet queue for the specified session: every packet
+	/// must be destined for a mixnode in the session, and the queue's internal invariants must
+	/// hold. Packets are always exactly [`PACKET_SIZE`] bytes; this is enforced by their type.
+	/// This is O(n * m) in the queue length and number of mixnodes, and is only available in
+	/// debug builds.
+	#[cfg(debug_assertions)]
+	pub fn authored_queue_verify_integrity(
+		&self,
+		rel_session_index: RelSessionIndex,
+	) -> Result<(), IntegrityErr> {
+		let session =
+			self.sessions[rel_session_index].as_option().ok_or(IntegrityErr::SessionNotActive)?;
+		session.authored_packet_queue.check_invariants().map_err(IntegrityErr::Invariant)?;
+		for (position, packet) in session.authored_packet_queue.iter().enumerate() {
+			if packet.peer_id == [0; PEER_ID_SIZE] {
+				return Err(IntegrityErr::NullPeerId(position))
+			}
+			if session.topology.mixnode_index_from_peer_id(&packet.peer_id).is_none() {
+				return Err(IntegrityErr::UnknownPeerId { position, peer_id: packet.peer_id })
+			}
+		}
 		Ok(())
 	}
 
+	/// Change the capacity of the authored packet queue for the specified session, which is
+	/// normally fixed by [`SessionConfig::authored_packet_queue`] when the session is created. If
+	/// the queue is longer than the new capacity, the oldest packets are dropped to fit. Returns
+	/// the number of packets dropped. Note that dropping packets will typically leave messages
+	/// incomplete.
+	pub fn authored_queue_set_capacity(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+		capacity: usize,
+	) -> Result<usize, CapacityErr> {
+		if capacity > MAX_AUTHORED_QUEUE_CAPACITY {
+			return Err(CapacityErr::TooLarge(capacity))
+		}
+		let queue = &mut self.sessions[rel_session_index]
+			.as_mut_option()
+			.ok_or(CapacityErr::SessionNotActive)?
+			.authored_packet_queue;
+		let prev_capacity = queue.capacity();
+		let num_dropped = queue.set_capacity(capacity);
+		if (capacity > prev_capacity) || (num_dropped > 0) {
+			self.events |= Events::SPACE_IN_AUTHORED_PACKET_QUEUE;
+		}
+		Ok(num_dropped)
+	}
+
+	/// Returns the number of packets in the authored packet queue for the specified session at
+	/// which the application should stop accepting new messages from users:
+	/// [`Config::backpressure_fill_ratio`] times the queue capacity, rounded down. Returns
+	/// [`None`] if the session is not active.
+	pub fn authored_queue_backpressure_threshold(
+		&self,
+		rel_session_index: RelSessionIndex,
+	) -> Option<usize> {
+		let queue = &self.sessions[rel_session_index].as_option()?.authored_packet_queue;
+		let capacity = queue.capacity();
+		Some(((capacity as f64) * self.config.backpressure_fill_ratio) as usize)
+	}
+
+	/// Release any memory not currently needed by the authored packet queue for the specified
+	/// session. Does nothing if the session is not active. This has no observable effect on
+	/// behaviour; it may be worth calling during periods of low traffic in long-running nodes.
+	///
+	/// Note that the queue is a ring buffer, so popping packets never leaves gaps; the only
+	/// saving is any storage beyond that needed for the currently queued packets. The storage is
+	/// normally allocated up front, so compacting will typically result in reallocations later,
+	/// as the queue fills up again.
+	pub fn authored_queue_compact(&mut self, rel_session_index: RelSessionIndex) {
+		if let Some(session) = self.sessions[rel_session_index].as_mut_option() {
+			session.authored_packet_queue.compact();
+		}
+	}
+
+	/// Pop the packet at the head of the authored packet queue for the specified session
+	/// immediately, rather than waiting for it to be sent by
+	/// [`pop_next_authored_packet`](Self::pop_next_authored_packet). Returns [`None`] if the
+	/// session is not active, the queue is empty or paused, or requests and replies are not
+	/// currently allowed in the session.
+	///
+	/// This significantly weakens anonymity: the packet is sent outside of the Poisson process
+	/// that normally hides when real packets are sent, so observers can distinguish it from cover
+	/// traffic. Only use this for time-sensitive messages where this is acceptable.
+	pub fn authored_queue_preemptive_pop(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+	) -> Option<AddressedPacket> {
+		if !self.session_status.phase.allow_requests_and_replies(rel_session_index) {
+			return None
+		}
+		let session = self.sessions[rel_session_index].as_mut_option()?;
+		if session.authored_packet_queue.is_paused() {
+			return None
+		}
+		let (packet, space) = session.authored_packet_queue.pop();
+		if space {
+			self.events |= Events::SPACE_IN_AUTHORED_PACKET_QUEUE;
+		}
+		if packet.is_some() {
+			warn!(target: self.config.log_target,
+				"Sending authored packet outside of the Poisson process; anonymity is reduced");
+			self.events |= Events::NEXT_AUTHORED_PACKET_DEADLINE_CHANGED;
+		}
+		packet
+	}
+
+	/// Pop the first real packet from the authored packet queue for the specified session.
+	/// Unlike [`pop_next_authored_packet`](Self::pop_next_authored_packet), this never generates
+	/// a cover packet; if there are no real packets in the queue, [`None`] is returned and the
+	/// caller must decide whether to send a cover packet itself (see
+	/// [`gen_cover_packet`](Self::gen_cover_packet)). Cover packets in the queue are skipped over
+	/// and left in place. Also returns [`None`] if the session is not active, the queue is paused,
+	/// or requests and replies are not currently allowed in the session.
+	///
+	/// Like `pop_next_authored_packet`, this should be called according to a Poisson process
+	/// (see [`next_authored_packet_delay`](Self::next_authored_packet_delay)). If real packets
+	/// are only sent when available and nothing is sent otherwise, observers can trivially tell
+	/// when real packets are being sent.
+	pub fn authored_queue_pop_real_only(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+	) -> Option<AddressedPacket> {
+		if !self.session_status.phase.allow_requests_and_replies(rel_session_index) {
+			return None
+		}
+		let session = self.sessions[rel_session_index].as_mut_option()?;
+		if session.authored_packet_queue.is_paused() {
+			return None
+		}
+		let (packet, space) = session.authored_packet_queue.pop_first_real();
+		if space {
+			self.events |= Events::SPACE_IN_AUTHORED_PACKET_QUEUE;
+		}
+		if packet.is_some() {
+			self.events |= Events::NEXT_AUTHORED_PACKET_DEADLINE_CHANGED;
+		}
+		packet
+	}
+
+	/// Returns a future which resolves the next time a packet is pushed onto the authored packet
+	/// queue of any session, or [`None`] if there are no active sessions. This allows async
+	/// callers to wait for authored packets instead of polling. Note that packets already in the
+	/// queues do not cause the future to resolve.
+	///
+	/// Packets should still be popped according to the authored packet schedule (see
+	/// [`next_authored_packet_delay`](Self::next_authored_packet_delay)); sending packets as soon
+	/// as they are queued would reveal when real packets are sent.
+	pub fn authored_queue_fill_event(&self) -> Option<WakerToken> {
+		let active = self.sessions.iter().next().is_some();
+		active.then(|| self.authored_queue_fill_notifier.token())
+	}
+
+	/// Pop the packet at the head of the authored packet queue for the specified session, but only
+	/// if it was queued before `deadline`. This can be used to enforce a maximum queueing
+	/// latency: if [`None`] is returned, the caller should send a cover packet instead (see
+	/// [`gen_cover_packet`](Self::gen_cover_packet)). The packet is left in the queue if it was
+	/// queued at or after `deadline`. Also returns [`None`] if the session is not active, the
+	/// queue is empty or paused, or requests and replies are not currently allowed in the
+	/// session.
+	pub fn authored_queue_pop_with_deadline(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+		deadline: Instant,
+	) -> Option<AddressedPacket> {
+		if !self.session_status.phase.allow_requests_and_replies(rel_session_index) {
+			return None
+		}
+		let session = self.sessions[rel_session_index].as_mut_option()?;
+		if session.authored_packet_queue.is_paused() {
+			return None
+		}
+		let (packet, space) = session.authored_packet_queue.pop_if_enqueued_before(deadline);
+		if space {
+			self.events |= Events::SPACE_IN_AUTHORED_PACKET_QUEUE;
+		}
+		packet
+	}
+
+	/// Pop the first packet in the authored packet queue for the specified session that is
+	/// addressed to `peer_id`. This allows per-connection backpressure: if the connection to one
+	/// peer is congested, the caller can stop popping packets for that peer while still popping
+	/// packets for others. Returns [`None`] if there is no such packet, the session is not active,
+	/// the queue is paused, or requests and replies are not currently allowed in the session.
+	///
+	/// As with [`authored_queue_pop_real_only`](Self::authored_queue_pop_real_only), no cover
+	/// packets are generated, so the caller is responsible for preserving the timing of authored
+	/// traffic. Note that this may reorder the fragments of a message.
+	pub fn authored_queue_pop_next_for_peer(
+		&mut self,
+		peer_id: &PeerId,
+		rel_session_index: RelSessionIndex,
+	) -> Option<AddressedPacket> {
+		if !self.session_status.phase.allow_requests_and_replies(rel_session_index) {
+			return None
+		}
+		let session = self.sessions[rel_session_index].as_mut_option()?;
+		if session.authored_packet_queue.is_paused() {
+			return None
+		}
+		let (packet, space) =
+			session.authored_packet_queue.pop_first_tagged(|packet| &packet.peer_id == peer_id);
+		if space {
+			self.events |= Events::SPACE_IN_AUTHORED_PACKET_QUEUE;
+		}
+		packet.map(|(packet, _tag)| packet)
+	}
+
+	/// Returns the number of bytes of packet buffer space needed to hold enough packets to fill
+	/// the authored packet queue for the specified session: the number of free slots times
+	/// [`PACKET_SIZE`]. Returns [`None`] if the session is not active. Note that this is an upper
+	/// bound on the space needed for new messages; it does not account for queue restrictions
+	/// such as [`SessionConfig::authored_packet_queue`] not allowing multiple messages.
+	pub fn authored_queue_byte_budget_remaining(
+		&self,
+		rel_session_index: RelSessionIndex,
+	) -> Option<usize> {
+		let queue = &self.sessions[rel_session_index].as_option()?.authored_packet_queue;
+		Some(queue.capacity().saturating_sub(queue.len()) * PACKET_SIZE)
+	}
+
+	/// Drop the oldest packets from the authored packet queue for the specified session until
+	/// there is space for `needed_slots` more packets. Returns the number of packets dropped.
+	/// Nothing is dropped if the session is not active, or if the queue capacity is less than
+	/// `needed_slots`. Note that dropping packets will typically leave messages incomplete.
+	pub fn authored_queue_drop_low_priority_to_fit(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+		needed_slots: usize,
+	) -> usize {
+		let Some(session) = self.sessions[rel_session_index].as_mut_option() else { return 0 };
+		let num_dropped = session.authored_packet_queue.drop_oldest_to_fit(needed_slots);
+		if num_dropped > 0 {
+			self.events |= Events::SPACE_IN_AUTHORED_PACKET_QUEUE;
+		}
+		num_dropped
+	}
+
+	/// Returns an iterator over the destination peer ID of each packet in the authored packet queue
+	/// for the specified session, from head to tail. Returns [`None`] if the session is not
+	/// active. This can be used to establish connections to peers ahead of time.
+	pub fn authored_queue_iter_peer_ids(
+		&self,
+		rel_session_index: RelSessionIndex,
+	) -> Option<impl Iterator<Item = &PeerId>> {
+		let session = self.sessions[rel_session_index].as_option()?;
+		Some(session.authored_packet_queue.iter().map(|packet| &packet.peer_id))
+	}
+
+	/// Set the policy used to evict packets from the authored packet queues when there is not
+	/// enough space for a new message. The default policy, [`NoEviction`], never evicts packets.
+	pub fn set_authored_queue_eviction_policy(
+		&mut self,
+		policy: Box<dyn AuthoredQueueEvictionPolicy>,
+	) {
+		self.authored_queue_eviction_policy = policy;
+	}
+
+	/// Returns statistics on how long the packets in the authored packet queue for the specified
+	/// session have been waiting. Returns [`None`] if the session is not active or the queue is
+	/// empty. A large `max_wait` relative to the expected queue delay indicates that old packets
+	/// are holding up newer ones.
+	pub fn authored_queue_latency_stats(
+		&self,
+		rel_session_index: RelSessionIndex,
+	) -> Option<LatencyStats> {
+		let queue = &self.sessions[rel_session_index].as_option()?.authored_packet_queue;
+		let now = Instant::now();
+		let mut waits = queue
+			.iter_with_enqueue_times()
+			.map(|(_, enqueued_at)| now.saturating_duration_since(enqueued_at));
+		let first = waits.next()?;
+		let mut stats =
+			LatencyStats { min_wait: first, max_wait: first, mean_wait: Duration::ZERO };
+		let mut total = first;
+		let mut count = 1;
+		for wait in waits {
+			stats.min_wait = min(stats.min_wait, wait);
+			stats.max_wait = max(stats.max_wait, wait);
+			total += wait;
+			count += 1;
+		}
+		stats.mean_wait = total / count;
+		Some(stats)
+	}
+
+	/// Serialize the packet at the head of the authored packet queue for the specified session,
+	/// without removing it, using [`AddressedPacket::to_bytes`]. This can be used to durably log
+	/// packets before they are sent. Returns [`None`] if the session is not active or the queue is
+	/// empty.
+	///
+	/// Note that [`pop_next_authored_packet`](Self::pop_next_authored_packet) does not
+	/// necessarily return the packet at the head of the queue; it may return a cover packet or a
+	/// packet from the other session's queue instead. Callers should compare the popped packet
+	/// against the logged one.
+	pub fn authored_queue_serialize_head(
+		&self,
+		rel_session_index: RelSessionIndex,
+	) -> Option<Vec<u8>> {
+		let queue = &self.sessions[rel_session_index].as_option()?.authored_packet_queue;
+		queue.iter().next().map(AddressedPacket::to_bytes)
+	}
+
+	/// SCALE-encode the packets in the authored packet queue for the specified session, so that
+	/// they can be restored with
+	/// [`authored_queue_decode_from_persistence`](Self::authored_queue_decode_from_persistence)
+	/// after a restart. Returns [`None`] if the session is not active.
+	///
+	/// The message ID, fragment index, and tag of each packet are persisted, but not the time it
+	/// was enqueued. Note that the SURB keystore is not persisted, so replies to restored requests
+	/// will not be decryptable. Packets can also only be usefully restored into the same session,
+	/// while the mixnodes still have their key-exchange keys for that session.
+	#[cfg(feature = "scale")]
+	pub fn authored_queue_encode_for_persistence(
+		&self,
+		rel_session_index: RelSessionIndex,
+	) -> Option<Vec<u8>> {
+		Some(self.sessions[rel_session_index].as_option()?.authored_packet_queue.encode())
+	}
+
+	/// Restore packets encoded by
+	/// [`authored_queue_encode_for_persistence`](Self::authored_queue_encode_for_persistence) into
+	/// the authored packet queue for the specified session. The packets are pushed onto the back
+	/// of the queue, and are considered to have been enqueued now. Space held by reservations (see
+	/// [`reserve_authored_queue_space`](Self::reserve_authored_queue_space)) is not used. Returns
+	/// the number of packets restored. Nothing is restored on error.
+	#[cfg(feature = "scale")]
+	pub fn authored_queue_decode_from_persistence(
+		&mut self,
+		bytes: &[u8],
+		rel_session_index: RelSessionIndex,
+	) -> Result<usize, PersistenceErr> {
+		let session = self.sessions[rel_session_index]
+			.as_mut_option()
+			.ok_or(PersistenceErr::SessionNotActive)?;
+		let num_restored = session.authored_packet_queue.decode_and_push(bytes)?;
+		if num_restored > 0 {
+			self.events |= Events::NEXT_AUTHORED_PACKET_DEADLINE_CHANGED;
+		}
+		Ok(num_restored)
+	}
+
+	/// Returns the destination and enqueue time of each packet in the authored packet queue for the
+	/// specified session, from head to tail. Returns [`None`] if the session is not active. This
+	/// is intended for debugging.
+	pub fn authored_queue_snapshot(
+		&self,
+		rel_session_index: RelSessionIndex,
+	) -> Option<Vec<(PeerId, Instant)>> {
+		let session = self.sessions[rel_session_index].as_option()?;
+		Some(
+			session
+				.authored_packet_queue
+				.iter_with_enqueue_times()
+				.map(|(packet, enqueued_at)| (packet.peer_id, enqueued_at))
+				.collect(),
+		)
+	}
+
+	/// Returns the length of the authored packet queue for the specified session divided by its
+	/// capacity, in the range 0 to 1. A queue with zero capacity is considered full. Returns
+	/// [`None`] if the session is not active.
+	pub fn authored_queue_fill_ratio(&self, rel_session_index: RelSessionIndex) -> Option<f64> {
+		let queue = &self.sessions[rel_session_index].as_option()?.authored_packet_queue;
+		Some(match queue.capacity() {
+			0 => 1.0,
+			capacity => (queue.len() as f64) / (capacity as f64),
+		})
+	}
+
+	/// Returns a rough estimate of the time it will take to drain the authored packet queue for the
+	/// specified session, or [`None`] if the session is not active. This is simply the queue
+	/// length multiplied by the session's mean authored packet period; it does not account for
+	/// loop cover packets or session transitions, both of which reduce the rate at which the queue
+	/// drains. See [`RequestMetrics`] for a more conservative estimate. Any clock skew set with
+	/// [`authored_queue_clock_skew_tolerance`](Self::authored_queue_clock_skew_tolerance) is
+	/// added to the estimate.
+	pub fn authored_queue_estimated_send_time(
+		&self,
+		rel_session_index: RelSessionIndex,
+	) -> Option<Duration> {
+		let session = self.sessions[rel_session_index].as_option()?;
+		Some(
+			(session.mean_authored_packet_period * (session.authored_packet_queue.len() as u32)) +
+				session.clock_skew,
+		)
+	}
+
+	/// Tell the mixnet that the local clock may be off by up to `skew` in the specified session.
+	/// The skew is accounted for by
+	/// [`authored_queue_estimated_send_time`](Self::authored_queue_estimated_send_time). The
+	/// session's mean authored packet period is not changed, as a constant clock offset does not
+	/// affect the intervals between packets. Returns `false` if the session is not active.
+	pub fn authored_queue_clock_skew_tolerance(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+		skew: Duration,
+	) -> bool {
+		let Some(session) = self.sessions[rel_session_index].as_mut_option() else {
+			return false
+		};
+		session.clock_skew = skew;
+		true
+	}
+
+	/// Returns the theoretical false positive rate of the replay filter for the specified session,
+	/// or [`None`] if the session is not active. Each false positive results in a packet being
+	/// wrongly dropped.
+	pub fn replay_filter_fpr(&self, rel_session_index: RelSessionIndex) -> Option<f64> {
+		self.sessions[rel_session_index]
+			.as_option()
+			.map(|session| session.replay_filter.false_positive_rate())
+	}
+
+	/// Pause packet dispatch for the specified session. While paused,
+	/// [`pop_next_authored_packet`](Self::pop_next_authored_packet) will return [`None`] whenever
+	/// it picks the session; neither real packets nor cover packets will be sent. Packets already
+	/// in the authored packet queue are kept. Does nothing if the session is not active.
+	///
+	/// Note that pausing a session makes the local node's traffic distinguishable from that of
+	/// other nodes, so this should only be used for short periods, eg during maintenance.
+	pub fn authored_queue_pause(&mut self, rel_session_index: RelSessionIndex) {
+		if let Some(session) = self.sessions[rel_session_index].as_mut_option() {
+			session.authored_packet_queue.set_paused(true);
+		}
+	}
+
+	/// Resume packet dispatch for the specified session after a call to
+	/// [`authored_queue_pause`](Self::authored_queue_pause). Does nothing if the session is not
+	/// active.
+	pub fn authored_queue_resume(&mut self, rel_session_index: RelSessionIndex) {
+		if let Some(session) = self.sessions[rel_session_index].as_mut_option() {
+			session.authored_packet_queue.set_paused(false);
+		}
+	}
+
 	/// Clear the event flags. Returns the flags that were cleared.
-	pub fn take_events(&mut self) -> Events {
+	pub fn take_and_clear_events(&mut self) -> Events {
 		let events = self.events;
 		self.events = Events::empty();
 		events
 	}
+
+	/// Clear the event flags. Returns the flags that were cleared.
+	#[deprecated(note = "Renamed to take_and_clear_events")]
+	pub fn take_events(&mut self) -> Events {
+		self.take_and_clear_events()
+	}
+
+	/// Returns the event flags, without clearing them.
+	pub fn events(&self) -> Events {
+		self.events
+	}
+
+	/// Clear only the specified event flags. This is useful if events are handled individually,
+	/// eg asynchronously; otherwise [`take_and_clear_events`](Self::take_and_clear_events) is
+	/// simpler.
+	pub fn clear_events(&mut self, events: Events) {
+		self.events.remove(events);
+	}
+}
+
+impl<X: Clone> Mixnet<X> {
+	/// Like [`maybe_set_mixnodes`](Self::maybe_set_mixnodes), but the mixnodes are fetched via
+	/// `cache`. If `cache` is [`None`] and the mixnodes are needed, `fetch()` is called and, on
+	/// success, the result is stored in `cache`. If `cache` is already populated, `fetch()` is not
+	/// called. This is useful when multiple `Mixnet` instances need the same mixnodes. Note that
+	/// the caller is responsible for clearing `cache` when the session changes.
+	pub fn maybe_set_mixnodes_cached(
+		&mut self,
+		rel_session_index: RelSessionIndex,
+		cache: &mut Option<Vec<Mixnode<X>>>,
+		fetch: &mut dyn FnMut() -> Result<Vec<Mixnode<X>>, MixnodesErr>,
+	) {
+		self.maybe_set_mixnodes(rel_session_index, &mut || {
+			if let Some(mixnodes) = cache {
+				return Ok(mixnodes.clone())
+			}
+			let mixnodes = fetch()?;
+			*cache = Some(mixnodes.clone());
+			Ok(mixnodes)
+		});
+	}
 }
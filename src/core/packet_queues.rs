@@ -20,14 +20,30 @@
 
 //! Mixnet packet queues.
 
-use super::sphinx::{Packet, PeerId};
+#[cfg(feature = "scale")]
+use super::PersistenceErr;
+use super::{
+	fragment::{div_ceil, MessageId},
+	sphinx::{Packet, PeerId, PACKET_SIZE, PEER_ID_SIZE},
+};
+use parking_lot::Mutex;
 use std::{
-	cmp::Ordering,
-	collections::{BinaryHeap, VecDeque},
-	time::Instant,
+	cmp::{min, Ordering},
+	collections::{BinaryHeap, HashSet, VecDeque},
+	future::Future,
+	io::{self, Write},
+	pin::Pin,
+	sync::{
+		atomic::{AtomicUsize, Ordering as AtomicOrdering},
+		Arc,
+	},
+	task::{Context, Poll, Waker},
+	time::{Duration, Instant},
 };
 
-/// A packet plus the ID of the peer it should be sent to.
+/// A packet plus the ID of the peer it should be sent to. Two `AddressedPacket`s are equal if they
+/// have the same destination and identical contents.
+#[derive(PartialEq, Eq, Hash)]
 pub struct AddressedPacket {
 	/// Where the packet should be sent.
 	pub peer_id: PeerId,
@@ -35,6 +51,57 @@ pub struct AddressedPacket {
 	pub packet: Box<Packet>,
 }
 
+impl AddressedPacket {
+	/// Write the packet contents to `writer`. Note that the peer ID is not written.
+	pub fn send_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		writer.write_all(self.packet.as_slice())
+	}
+
+	/// Returns the peer ID followed by the packet contents, [`PEER_ID_SIZE`] + [`PACKET_SIZE`]
+	/// bytes in total.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = Vec::with_capacity(PEER_ID_SIZE + PACKET_SIZE);
+		bytes.extend_from_slice(&self.peer_id);
+		bytes.extend_from_slice(self.packet.as_slice());
+		bytes
+	}
+
+	/// Returns `true` if `self` and `other` have identical contents. Unlike `==`, the
+	/// destinations are not compared.
+	pub fn is_same_packet_as(&self, other: &AddressedPacket) -> bool {
+		self.packet == other.packet
+	}
+}
+
+/// Forward packet queue statistics, for monitoring. See
+/// [`Mixnet::forward_queue_statistics`](super::Mixnet::forward_queue_statistics).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ForwardQueueStats {
+	/// Number of packets in the queue.
+	pub len: usize,
+	/// Maximum number of packets in the queue.
+	pub capacity: usize,
+	/// Earliest deadline of any packet in the queue.
+	pub next_deadline: Option<Instant>,
+	/// Latest deadline of any packet in the queue.
+	pub furthest_deadline: Option<Instant>,
+	/// Mean time until the packets in the queue should be sent. Packets with deadlines in the past
+	/// count as zero.
+	pub mean_deadline_from_now: Option<Duration>,
+}
+
+/// Percentiles of the times until the packets in the forward packet queue should be sent. See
+/// [`Mixnet::forward_queue_deadline_statistics`](super::Mixnet::forward_queue_deadline_statistics).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ForwardQueueDeadlineStats {
+	/// Median time until a packet should be sent.
+	pub p50_from_now: Duration,
+	/// 95th percentile time until a packet should be sent.
+	pub p95_from_now: Duration,
+	/// 99th percentile time until a packet should be sent.
+	pub p99_from_now: Duration,
+}
+
 /// `Eq` and `Ord` are implemented for this to support use in `BinaryHeap`s. Only `deadline` is
 /// compared.
 struct ForwardPacket {
@@ -81,10 +148,20 @@ impl ForwardPacketQueue {
 		self.queue.peek().map(|packet| packet.deadline)
 	}
 
+	pub fn len(&self) -> usize {
+		self.queue.len()
+	}
+
 	pub fn has_space(&self) -> bool {
 		self.queue.len() < self.capacity
 	}
 
+	/// Release any storage not currently needed to hold queued packets. The queue will grow
+	/// again as packets are inserted.
+	pub fn compact(&mut self) {
+		self.queue.shrink_to_fit();
+	}
+
 	/// Insert a packet into the queue. Returns `true` iff the deadline of the item at the head of
 	/// the queue changed. Should only be called if there is space in the queue (see
 	/// [`has_space`](Self::has_space)).
@@ -98,17 +175,201 @@ impl ForwardPacketQueue {
 	pub fn pop(&mut self) -> Option<AddressedPacket> {
 		self.queue.pop().map(|packet| packet.packet)
 	}
+
+	/// Compute statistics over all packets in the queue. This is O(n) in the queue length.
+	pub fn statistics(&self, now: Instant) -> ForwardQueueStats {
+		let furthest_deadline = self.queue.iter().map(|packet| packet.deadline).max();
+		let mean_deadline_from_now = (!self.queue.is_empty()).then(|| {
+			let total: Duration = self
+				.queue
+				.iter()
+				.map(|packet| packet.deadline.saturating_duration_since(now))
+				.sum();
+			total / (self.queue.len() as u32)
+		});
+		ForwardQueueStats {
+			len: self.queue.len(),
+			capacity: self.capacity,
+			next_deadline: self.next_deadline(),
+			furthest_deadline,
+			mean_deadline_from_now,
+		}
+	}
+
+	/// Compute percentiles of the times until the packets in the queue should be sent, using the
+	/// nearest-rank method. Packets with deadlines in the past count as zero. Returns [`None`] if
+	/// the queue is empty. This is O(n log n) in the queue length.
+	pub fn deadline_statistics(&self, now: Instant) -> Option<ForwardQueueDeadlineStats> {
+		let mut from_now: Vec<_> = self
+			.queue
+			.iter()
+			.map(|packet| packet.deadline.saturating_duration_since(now))
+			.collect();
+		if from_now.is_empty() {
+			return None
+		}
+		from_now.sort_unstable();
+		let percentile = |p: usize| {
+			// Nearest rank is ceil(p/100 * n), which is 1-based
+			let rank = div_ceil(p * from_now.len(), 100);
+			from_now[rank.max(1) - 1]
+		};
+		Some(ForwardQueueDeadlineStats {
+			p50_from_now: percentile(50),
+			p95_from_now: percentile(95),
+			p99_from_now: percentile(99),
+		})
+	}
+
+	/// Postpone the deadlines of all packets destined for `peer_id` by `delta`. Returns the
+	/// number of packets that were rescheduled.
+	pub fn reschedule(&mut self, peer_id: &PeerId, delta: Duration) -> usize {
+		let mut packets = std::mem::take(&mut self.queue).into_vec();
+		let mut num_rescheduled = 0;
+		for packet in &mut packets {
+			if &packet.packet.peer_id == peer_id {
+				packet.deadline += delta;
+				num_rescheduled += 1;
+			}
+		}
+		self.queue = packets.into(); // Rebuilds the heap
+		num_rescheduled
+	}
 }
 
+/// Maximum capacity of an authored packet queue that can be set at runtime. See
+/// [`Mixnet::authored_queue_set_capacity`](super::Mixnet::authored_queue_set_capacity).
+pub const MAX_AUTHORED_QUEUE_CAPACITY: usize = 10_000;
+
 #[derive(Clone, Copy, Debug)]
 pub struct AuthoredPacketQueueConfig {
-	/// Maximum number of packets in the queue. Note that cover packets do not go in the queue;
-	/// they are generated on demand.
+	/// Maximum number of packets in the queue. Note that cover packets do not normally go in the
+	/// queue; they are generated on demand.
 	pub capacity: usize,
 	/// Allow packets for multiple messages in the queue?
 	pub multiple_messages: bool,
 }
 
+/// Decides which packets to evict from an authored packet queue when there is not enough space
+/// for a new message. Note that evicting a packet will typically leave the message it belongs to
+/// incomplete.
+pub trait AuthoredQueueEvictionPolicy: Send + Sync {
+	/// Returns the index of the packet in `queue` (which is ordered from head to tail) to evict,
+	/// or [`None`] if no packet should be evicted. This is called repeatedly until either there is
+	/// enough space or [`None`] is returned.
+	fn should_evict(&self, queue: &[&AddressedPacket]) -> Option<usize>;
+}
+
+/// Never evict packets; new messages are rejected when the queue is full. This is the default.
+pub struct NoEviction;
+
+impl AuthoredQueueEvictionPolicy for NoEviction {
+	fn should_evict(&self, _queue: &[&AddressedPacket]) -> Option<usize> {
+		None
+	}
+}
+
+/// Evict the oldest packets (those at the head of the queue) to make space for new messages.
+pub struct EvictOldest;
+
+impl AuthoredQueueEvictionPolicy for EvictOldest {
+	fn should_evict(&self, queue: &[&AddressedPacket]) -> Option<usize> {
+		(!queue.is_empty()).then_some(0)
+	}
+}
+
+/// Packets to evict from an authored packet queue. See
+/// [`AuthoredPacketQueue::plan_eviction`].
+pub struct EvictionPlan {
+	/// One entry per packet in the queue, from head to tail; `false` for packets to evict.
+	keep: Vec<bool>,
+}
+
+/// Space reserved in an authored packet queue. See
+/// [`Mixnet::reserve_authored_queue_space`](super::Mixnet::reserve_authored_queue_space). Any
+/// space still held is released when the reservation is dropped.
+#[derive(Debug)]
+pub struct Reservation {
+	/// Total number of slots reserved in the queue. Shared with the queue.
+	queue_reserved: Arc<AtomicUsize>,
+	/// Number of slots held by this reservation.
+	count: usize,
+}
+
+impl Reservation {
+	/// Returns the number of packet slots still held by the reservation.
+	pub fn count(&self) -> usize {
+		self.count
+	}
+
+	fn release(&mut self, count: usize) {
+		let count = min(count, self.count);
+		self.count -= count;
+		self.queue_reserved.fetch_sub(count, AtomicOrdering::Relaxed);
+	}
+}
+
+impl Drop for Reservation {
+	fn drop(&mut self) {
+		self.release(self.count);
+	}
+}
+
+#[derive(Default)]
+struct FillNotifierState {
+	/// Incremented every time a packet is pushed onto a queue.
+	generation: u64,
+	/// Wakers to wake when the generation is next incremented.
+	wakers: Vec<Waker>,
+}
+
+/// Notifies [`WakerToken`]s when packets are pushed onto authored packet queues. Can be shared
+/// between queues.
+#[derive(Clone, Default)]
+pub struct FillNotifier(Arc<Mutex<FillNotifierState>>);
+
+impl FillNotifier {
+	/// Returns a future which will resolve the next time [`notify`](Self::notify) is called.
+	pub fn token(&self) -> WakerToken {
+		let generation = self.0.lock().generation;
+		WakerToken { state: self.0.clone(), generation }
+	}
+
+	fn notify(&self) {
+		let wakers = {
+			let mut state = self.0.lock();
+			state.generation = state.generation.wrapping_add(1);
+			std::mem::take(&mut state.wakers)
+		};
+		for waker in wakers {
+			waker.wake();
+		}
+	}
+}
+
+/// Future which resolves the next time a packet is pushed onto an authored packet queue. See
+/// [`Mixnet::authored_queue_fill_event`](super::Mixnet::authored_queue_fill_event).
+pub struct WakerToken {
+	state: Arc<Mutex<FillNotifierState>>,
+	/// Generation at the time the token was created.
+	generation: u64,
+}
+
+impl Future for WakerToken {
+	type Output = ();
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+		let mut state = self.state.lock();
+		if state.generation != self.generation {
+			return Poll::Ready(())
+		}
+		if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+			state.wakers.push(cx.waker().clone());
+		}
+		Poll::Pending
+	}
+}
+
 pub enum CheckSpaceErr {
 	/// There will never be enough space.
 	Capacity,
@@ -116,47 +377,925 @@ pub enum CheckSpaceErr {
 	Len,
 }
 
+struct AuthoredPacket {
+	/// When the packet was pushed onto the queue.
+	enqueued_at: Instant,
+	/// `true` if the packet is a cover packet (see
+	/// [`replace_real_with_cover`](AuthoredPacketQueue::replace_real_with_cover)).
+	is_cover: bool,
+	/// The message the packet belongs to, if known. Always [`None`] for cover packets.
+	message_id: Option<MessageId>,
+	/// Index of the fragment carried by the packet within the message. Only meaningful if
+	/// `message_id` is not [`None`].
+	fragment_index: usize,
+	/// Caller-supplied tag; see [`push_tagged`](AuthoredPacketQueue::push_tagged). 0 if none.
+	tag: u64,
+	/// The packet and destination.
+	packet: AddressedPacket,
+}
+
+/// Persisted form of an [`AuthoredPacket`]: whether it is a cover packet, the message ID, the
+/// fragment index, the tag, the destination, and the packet data.
+#[cfg(feature = "scale")]
+type PersistedPacket<Data> = (bool, Option<MessageId>, u64, u64, PeerId, Data);
+
 pub struct AuthoredPacketQueue {
 	config: AuthoredPacketQueueConfig,
-	queue: VecDeque<AddressedPacket>,
+	queue: VecDeque<AuthoredPacket>,
+	/// If `true`, nothing (neither packets from the queue nor cover packets) should be sent in
+	/// the session.
+	paused: bool,
+	/// Number of slots held by [`Reservation`]s.
+	reserved: Arc<AtomicUsize>,
+	/// Notified whenever a packet is pushed.
+	fill_notifier: FillNotifier,
 }
 
 impl AuthoredPacketQueue {
+	#[cfg(test)]
 	pub fn new(config: AuthoredPacketQueueConfig) -> Self {
-		Self { config, queue: VecDeque::with_capacity(config.capacity) }
+		Self::with_fill_notifier(config, FillNotifier::default())
+	}
+
+	/// Like [`new`](Self::new), but `fill_notifier` will be notified whenever a packet is pushed
+	/// onto the queue.
+	pub fn with_fill_notifier(
+		config: AuthoredPacketQueueConfig,
+		fill_notifier: FillNotifier,
+	) -> Self {
+		Self {
+			config,
+			queue: VecDeque::with_capacity(config.capacity),
+			paused: false,
+			reserved: Arc::new(AtomicUsize::new(0)),
+			fill_notifier,
+		}
+	}
+
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
+
+	pub fn set_paused(&mut self, paused: bool) {
+		self.paused = paused;
 	}
 
 	pub fn len(&self) -> usize {
 		self.queue.len()
 	}
 
+	pub fn capacity(&self) -> usize {
+		self.config.capacity
+	}
+
+	/// Returns an iterator over the packets in the queue, from head to tail.
+	pub fn iter(&self) -> impl Iterator<Item = &AddressedPacket> {
+		self.queue.iter().map(|packet| &packet.packet)
+	}
+
+	/// Check the internal invariants of the queue. Returns a description of the first violated
+	/// invariant, if any.
+	#[cfg(debug_assertions)]
+	pub fn check_invariants(&self) -> Result<(), &'static str> {
+		if self.queue.len() > self.config.capacity {
+			return Err("Queue length exceeds capacity")
+		}
+		if self.queue.iter().any(|packet| packet.is_cover && packet.message_id.is_some()) {
+			return Err("Cover packet has a message ID")
+		}
+		Ok(())
+	}
+
+	/// Like [`iter`](Self::iter), but also yields the time at which each packet was pushed onto
+	/// the queue.
+	pub fn iter_with_enqueue_times(&self) -> impl Iterator<Item = (&AddressedPacket, Instant)> {
+		self.queue.iter().map(|packet| (&packet.packet, packet.enqueued_at))
+	}
+
+	/// SCALE-encode the packets in the queue, from head to tail, so that they can be restored
+	/// with [`decode_and_push`](Self::decode_and_push). Whether each packet is a cover packet, and
+	/// the message ID, fragment index, and tag of each packet, are included. Enqueue times are
+	/// not.
+	#[cfg(feature = "scale")]
+	pub fn encode(&self) -> Vec<u8> {
+		let entries: Vec<PersistedPacket<&[u8]>> = self
+			.queue
+			.iter()
+			.map(|packet| {
+				(
+					packet.is_cover,
+					packet.message_id,
+					packet.fragment_index as u64,
+					packet.tag,
+					packet.packet.peer_id,
+					packet.packet.packet.as_slice(),
+				)
+			})
+			.collect();
+		codec::Encode::encode(&entries)
+	}
+
+	/// Decode packets encoded by [`encode`](Self::encode) and push them onto the back of the
+	/// queue. The enqueue time of each packet is set to now. Returns the number of packets pushed.
+	/// Nothing is pushed on error. Space held by reservations is not used.
+	#[cfg(feature = "scale")]
+	pub fn decode_and_push(&mut self, mut bytes: &[u8]) -> Result<usize, PersistenceErr> {
+		let entries: Vec<PersistedPacket<Vec<u8>>> = codec::DecodeAll::decode_all(&mut bytes)?;
+		let entries = entries
+			.into_iter()
+			.map(|(is_cover, message_id, fragment_index, tag, peer_id, packet)| {
+				let len = packet.len();
+				let packet: Box<Packet> = packet
+					.into_boxed_slice()
+					.try_into()
+					.map_err(|_| PersistenceErr::BadPacketSize(len))?;
+				let fragment_index = fragment_index
+					.try_into()
+					.map_err(|_| PersistenceErr::BadFragmentIndex(fragment_index))?;
+				Ok((AddressedPacket { peer_id, packet }, is_cover, message_id, fragment_index, tag))
+			})
+			.collect::<Result<Vec<_>, PersistenceErr>>()?;
+		let reserved = self.reserved.load(AtomicOrdering::Relaxed);
+		if self.queue.len().saturating_add(reserved).saturating_add(entries.len()) >
+			self.config.capacity
+		{
+			return Err(PersistenceErr::NotEnoughSpaceInQueue)
+		}
+		let num_pushed = entries.len();
+		for (packet, is_cover, message_id, fragment_index, tag) in entries {
+			self.push_authored(packet, is_cover, message_id, fragment_index, tag);
+		}
+		Ok(num_pushed)
+	}
+
+	/// Returns `true` if there are any real (non-cover) packets in the queue.
+	pub fn contains_real_packets(&self) -> bool {
+		self.queue.iter().any(|packet| !packet.is_cover)
+	}
+
+	/// Remove all packets pushed onto the queue before `cutoff`. Returns the destinations of the
+	/// removed packets, oldest first.
+	pub fn remove_enqueued_before(&mut self, cutoff: Instant) -> Vec<PeerId> {
+		let mut removed = Vec::new();
+		self.queue.retain(|packet| {
+			let keep = packet.enqueued_at >= cutoff;
+			if !keep {
+				removed.push(packet.packet.peer_id);
+			}
+			keep
+		});
+		removed
+	}
+
+	/// Remove all packets destined for `peer_id`. Returns the number of packets removed.
+	pub fn remove_for_peer(&mut self, peer_id: &PeerId) -> usize {
+		let len = self.queue.len();
+		self.queue.retain(|packet| &packet.packet.peer_id != peer_id);
+		len - self.queue.len()
+	}
+
+	/// Move all packets belonging to the message with the given ID to the front of the queue,
+	/// ordered by fragment index. The order of the other packets is preserved. Returns the number
+	/// of packets moved.
+	pub fn move_message_to_front(&mut self, message_id: &MessageId) -> usize {
+		let (mut moved, rest): (Vec<_>, Vec<_>) = self
+			.queue
+			.drain(..)
+			.partition(|packet| packet.message_id.as_ref() == Some(message_id));
+		moved.sort_by_key(|packet| packet.fragment_index);
+		let num_moved = moved.len();
+		self.queue.extend(moved);
+		self.queue.extend(rest);
+		num_moved
+	}
+
+	/// Remove all but the first packet carrying each fragment of each message. Packets not known
+	/// to belong to a message are left alone. Returns the number of packets removed.
+	pub fn deduplicate(&mut self) -> usize {
+		let len = self.queue.len();
+		let mut seen = HashSet::new();
+		self.queue.retain(|packet| match packet.message_id {
+			Some(message_id) => seen.insert((message_id, packet.fragment_index)),
+			None => true,
+		});
+		len - self.queue.len()
+	}
+
+	/// Returns `true` if there are any packets in the queue belonging to the message with the
+	/// given ID.
+	pub fn contains_message(&self, message_id: &MessageId) -> bool {
+		self.queue.iter().any(|packet| packet.message_id.as_ref() == Some(message_id))
+	}
+
+	/// Replace each real packet in the queue with the cover packet returned by `gen_cover`. If
+	/// `gen_cover` returns [`None`], the real packet is simply removed. Returns `true` if
+	/// [`check_space`](Self::check_space) might now succeed where it wouldn't before.
+	pub fn replace_real_with_cover(
+		&mut self,
+		gen_cover: impl FnMut(&AddressedPacket) -> Option<AddressedPacket>,
+	) -> bool {
+		self.replace_with_cover(|_| true, gen_cover).1
+	}
+
+	/// Like [`replace_real_with_cover`](Self::replace_real_with_cover), but only replaces packets
+	/// belonging to the message with the given ID. Returns the number of packets replaced (not
+	/// including any removed), and `true` if [`check_space`](Self::check_space) might now succeed
+	/// where it wouldn't before.
+	pub fn replace_message_with_cover(
+		&mut self,
+		message_id: &MessageId,
+		gen_cover: impl FnMut(&AddressedPacket) -> Option<AddressedPacket>,
+	) -> (usize, bool) {
+		self.replace_with_cover(|packet| packet.message_id.as_ref() == Some(message_id), gen_cover)
+	}
+
+	/// Replace real packets for which `select` returns `true`; see
+	/// [`replace_message_with_cover`](Self::replace_message_with_cover).
+	fn replace_with_cover(
+		&mut self,
+		mut select: impl FnMut(&AuthoredPacket) -> bool,
+		mut gen_cover: impl FnMut(&AddressedPacket) -> Option<AddressedPacket>,
+	) -> (usize, bool) {
+		let len = self.queue.len();
+		let mut num_replaced = 0;
+		self.queue.retain_mut(|packet| {
+			if packet.is_cover || !select(packet) {
+				return true
+			}
+			match gen_cover(&packet.packet) {
+				Some(cover_packet) => {
+					packet.packet = cover_packet;
+					packet.is_cover = true;
+					packet.message_id = None;
+					num_replaced += 1;
+					true
+				},
+				None => false,
+			}
+		});
+		let space =
+			(self.queue.len() != len) && (self.config.multiple_messages || self.queue.is_empty());
+		(num_replaced, space)
+	}
+
 	pub fn check_space(&self, num_packets: usize) -> Result<(), CheckSpaceErr> {
+		self.check_space_with_reservation(num_packets, None)
+	}
+
+	/// Like [`check_space`](Self::check_space), but the packets may use up to `num_packets` of
+	/// the slots held by `reservation`, which must have been made by this queue (see
+	/// [`owns_reservation`](Self::owns_reservation)).
+	pub fn check_space_with_reservation(
+		&self,
+		num_packets: usize,
+		reservation: Option<&Reservation>,
+	) -> Result<(), CheckSpaceErr> {
+		self.check_space_for_len(self.queue.len(), num_packets, reservation)
+	}
+
+	/// Like [`check_space_with_reservation`](Self::check_space_with_reservation), but assumes the
+	/// queue contains `len` packets.
+	fn check_space_for_len(
+		&self,
+		len: usize,
+		num_packets: usize,
+		reservation: Option<&Reservation>,
+	) -> Result<(), CheckSpaceErr> {
 		let Some(mut max_len) = self.config.capacity.checked_sub(num_packets) else {
 			return Err(CheckSpaceErr::Capacity)
 		};
 		if !self.config.multiple_messages {
 			max_len = 0;
 		}
-		if self.queue.len() > max_len {
+		let mut reserved = self.reserved.load(AtomicOrdering::Relaxed);
+		if let Some(reservation) = reservation {
+			debug_assert!(self.owns_reservation(reservation));
+			reserved = reserved.saturating_sub(min(reservation.count, num_packets));
+		}
+		if len.saturating_add(reserved) > max_len {
 			Err(CheckSpaceErr::Len)
 		} else {
 			Ok(())
 		}
 	}
 
-	/// Push a packet onto the queue. Should only be called if there is space in the queue (see
-	/// [`check_space`](Self::check_space)).
+	/// Reserve space for `count` packets in the queue. Until the returned reservation is dropped
+	/// or used (see [`use_reservation`](Self::use_reservation)), [`check_space`](Self::check_space)
+	/// behaves as if the queue contained `count` more packets.
+	pub fn reserve(&mut self, count: usize) -> Result<Reservation, CheckSpaceErr> {
+		self.check_space(count)?;
+		self.reserved.fetch_add(count, AtomicOrdering::Relaxed);
+		Ok(Reservation { queue_reserved: self.reserved.clone(), count })
+	}
+
+	/// Returns `true` if `reservation` was made by this queue.
+	pub fn owns_reservation(&self, reservation: &Reservation) -> bool {
+		Arc::ptr_eq(&reservation.queue_reserved, &self.reserved)
+	}
+
+	/// Release up to `count` slots held by `reservation`, which have been used by packets just
+	/// pushed. Returns `false`, leaving `reservation` untouched, if it was not made by this
+	/// queue.
+	pub fn use_reservation(&mut self, reservation: &mut Reservation, count: usize) -> bool {
+		if !self.owns_reservation(reservation) {
+			return false
+		}
+		reservation.release(count);
+		true
+	}
+
+	/// Change the capacity of the queue. If the queue is longer than the new capacity, packets
+	/// are dropped from the head of the queue (the oldest packets) to fit. Returns the number of
+	/// packets dropped.
+	pub fn set_capacity(&mut self, capacity: usize) -> usize {
+		self.config.capacity = capacity;
+		let num_dropped = self.queue.len().saturating_sub(capacity);
+		self.queue.drain(..num_dropped);
+		num_dropped
+	}
+
+	/// Release any storage not currently needed to hold queued packets. The queue will grow
+	/// again as packets are pushed.
+	pub fn compact(&mut self) {
+		self.queue.shrink_to_fit();
+	}
+
+	/// Drop packets from the head of the queue (the oldest packets) until
+	/// [`check_space(num_packets)`](Self::check_space) succeeds. If there will never be enough
+	/// space, nothing is dropped. Returns the number of packets dropped. Note that if there are
+	/// outstanding reservations, `check_space` may still fail after the queue has been emptied.
+	pub fn drop_oldest_to_fit(&mut self, num_packets: usize) -> usize {
+		let mut num_dropped = 0;
+		while let Err(CheckSpaceErr::Len) = self.check_space(num_packets) {
+			if self.queue.pop_front().is_none() {
+				break
+			}
+			num_dropped += 1;
+		}
+		num_dropped
+	}
+
+	/// Work out which packets to evict, as directed by `policy`, so that
+	/// [`check_space_with_reservation(num_packets, reservation)`](Self::check_space_with_reservation)
+	/// would succeed. Nothing is actually evicted until the returned plan is passed to
+	/// [`evict`](Self::evict). Fails with the error the space check would return if there is not
+	/// enough space even after evicting every packet `policy` chooses. Planning stops if `policy`
+	/// declines to evict any more packets or returns an out-of-range index.
+	pub fn plan_eviction(
+		&self,
+		num_packets: usize,
+		reservation: Option<&Reservation>,
+		policy: &dyn AuthoredQueueEvictionPolicy,
+	) -> Result<EvictionPlan, CheckSpaceErr> {
+		// Let the policy choose from a view of the queue
+		let mut view: Vec<_> = self.iter().collect();
+		let mut view_indices: Vec<_> = (0..view.len()).collect();
+		let mut keep = vec![true; view.len()];
+		while let Err(CheckSpaceErr::Len) =
+			self.check_space_for_len(view.len(), num_packets, reservation)
+		{
+			let Some(index) = policy.should_evict(&view) else { break };
+			if index >= view.len() {
+				break
+			}
+			view.remove(index);
+			keep[view_indices.remove(index)] = false;
+		}
+		self.check_space_for_len(view.len(), num_packets, reservation)?;
+		Ok(EvictionPlan { keep })
+	}
+
+	/// Evict the packets chosen by [`plan_eviction`](Self::plan_eviction). The queue must not
+	/// have been modified since the plan was made. Returns the number of packets evicted.
+	pub fn evict(&mut self, plan: EvictionPlan) -> usize {
+		debug_assert_eq!(plan.keep.len(), self.queue.len());
+		let num_evicted = plan.keep.iter().filter(|keep| !**keep).count();
+		if num_evicted > 0 {
+			let mut keep = plan.keep.into_iter();
+			self.queue.retain(|_| keep.next().expect("One entry per packet"));
+		}
+		num_evicted
+	}
+
+	/// Like [`push_with_message_id`](Self::push_with_message_id), but the packet does not
+	/// belong to any particular message.
+	#[cfg(test)]
 	pub fn push(&mut self, packet: AddressedPacket) {
+		self.push_authored(packet, false, None, 0, 0);
+	}
+
+	/// Push a packet carrying fragment `fragment_index` of the message with the given ID onto
+	/// the queue. Should only be called if there is space in the queue (see
+	/// [`check_space`](Self::check_space)).
+	pub fn push_with_message_id(
+		&mut self,
+		packet: AddressedPacket,
+		message_id: &MessageId,
+		fragment_index: usize,
+	) {
+		self.push_authored(packet, false, Some(*message_id), fragment_index, 0);
+	}
+
+	/// Like [`push_with_message_id`](Self::push_with_message_id), but also associates `tag` with
+	/// the packet. The tag is returned by [`pop_first_tagged`](Self::pop_first_tagged).
+	pub fn push_tagged(
+		&mut self,
+		packet: AddressedPacket,
+		message_id: &MessageId,
+		fragment_index: usize,
+		tag: u64,
+	) {
+		self.push_authored(packet, false, Some(*message_id), fragment_index, tag);
+	}
+
+	fn push_authored(
+		&mut self,
+		packet: AddressedPacket,
+		is_cover: bool,
+		message_id: Option<MessageId>,
+		fragment_index: usize,
+		tag: u64,
+	) {
 		debug_assert!(self.queue.len() < self.config.capacity);
-		self.queue.push_back(packet);
+		self.queue.push_back(AuthoredPacket {
+			enqueued_at: Instant::now(),
+			is_cover,
+			message_id,
+			fragment_index,
+			tag,
+			packet,
+		});
+		self.fill_notifier.notify();
 	}
 
 	/// Pop the packet at the head of the queue and return it, or, if the queue is empty, return
 	/// `None`. Also returns `true` if [`check_space`](Self::check_space) might now succeed where
 	/// it wouldn't before.
 	pub fn pop(&mut self) -> (Option<AddressedPacket>, bool) {
-		let packet = self.queue.pop_front();
+		let packet = self.queue.pop_front().map(|packet| packet.packet);
 		let space = packet.is_some() && (self.config.multiple_messages || self.queue.is_empty());
 		(packet, space)
 	}
+
+	/// Like [`pop`](Self::pop), but pops the first packet in the queue for which `pred` returns
+	/// `true`, rather than the packet at the head, and also returns the tag associated with the
+	/// packet (see [`push_tagged`](Self::push_tagged)).
+	pub fn pop_first_tagged(
+		&mut self,
+		mut pred: impl FnMut(&AddressedPacket) -> bool,
+	) -> (Option<(AddressedPacket, u64)>, bool) {
+		let (packet, space) = self.pop_first_authored(|packet| pred(&packet.packet));
+		(packet.map(|packet| (packet.packet, packet.tag)), space)
+	}
+
+	/// Like [`pop`](Self::pop), but only pops the packet at the head of the queue if it was
+	/// pushed onto the queue before `deadline`.
+	pub fn pop_if_enqueued_before(&mut self, deadline: Instant) -> (Option<AddressedPacket>, bool) {
+		match self.queue.front() {
+			Some(packet) if packet.enqueued_at < deadline => self.pop(),
+			_ => (None, false),
+		}
+	}
+
+	/// Like [`pop`](Self::pop), but pops the first real (non-cover) packet in the queue, rather
+	/// than the packet at the head.
+	pub fn pop_first_real(&mut self) -> (Option<AddressedPacket>, bool) {
+		let (packet, space) = self.pop_first_authored(|packet| !packet.is_cover);
+		(packet.map(|packet| packet.packet), space)
+	}
+
+	fn pop_first_authored(
+		&mut self,
+		pred: impl FnMut(&AuthoredPacket) -> bool,
+	) -> (Option<AuthoredPacket>, bool) {
+		let packet = self.queue.iter().position(pred).and_then(|i| self.queue.remove(i));
+		let space = packet.is_some() && (self.config.multiple_messages || self.queue.is_empty());
+		(packet, space)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn addressed_packet(peer_id: PeerId) -> AddressedPacket {
+		AddressedPacket { peer_id, packet: Box::new([0; PACKET_SIZE]) }
+	}
+
+	#[test]
+	fn addressed_packet_dedup() {
+		let mut other_packet = addressed_packet([0; 32]);
+		other_packet.packet[0] = 1;
+		assert!(!addressed_packet([0; 32]).is_same_packet_as(&other_packet));
+		assert!(addressed_packet([0; 32]).is_same_packet_as(&addressed_packet([1; 32])));
+
+		let packets: HashSet<_> = [
+			addressed_packet([0; 32]),
+			addressed_packet([1; 32]),
+			addressed_packet([0; 32]),
+			other_packet,
+		]
+		.into_iter()
+		.collect();
+		assert_eq!(packets.len(), 3);
+	}
+
+	#[test]
+	fn forward_packet_queue_statistics() {
+		let now = Instant::now();
+		let mut queue = ForwardPacketQueue::new(4);
+		let stats = queue.statistics(now);
+		assert_eq!((stats.len, stats.capacity), (0, 4));
+		assert_eq!(stats.mean_deadline_from_now, None);
+
+		queue.insert(now + Duration::from_secs(3), addressed_packet([0; 32]));
+		queue.insert(now + Duration::from_secs(1), addressed_packet([1; 32]));
+		let stats = queue.statistics(now);
+		assert_eq!(stats.len, 2);
+		assert_eq!(stats.next_deadline, Some(now + Duration::from_secs(1)));
+		assert_eq!(stats.furthest_deadline, Some(now + Duration::from_secs(3)));
+		assert_eq!(stats.mean_deadline_from_now, Some(Duration::from_secs(2)));
+	}
+
+	#[test]
+	fn forward_packet_queue_deadline_statistics() {
+		let now = Instant::now();
+		let mut queue = ForwardPacketQueue::new(100);
+		assert_eq!(queue.deadline_statistics(now), None);
+		for secs in 1..=100 {
+			queue.insert(now + Duration::from_secs(secs), addressed_packet([0; 32]));
+		}
+		let stats = queue.deadline_statistics(now).unwrap();
+		assert_eq!(stats.p50_from_now, Duration::from_secs(50));
+		assert_eq!(stats.p95_from_now, Duration::from_secs(95));
+		assert_eq!(stats.p99_from_now, Duration::from_secs(99));
+	}
+
+	#[test]
+	fn authored_packet_queue_replace_real_with_cover() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 3,
+			multiple_messages: true,
+		});
+		queue.push(addressed_packet([0; 32]));
+		queue.push(addressed_packet([1; 32]));
+		queue.push(addressed_packet([2; 32]));
+		assert!(queue.contains_real_packets());
+
+		// Drop the packet for peer 1, replace the others with "cover" for peer 3
+		let space = queue.replace_real_with_cover(|packet| {
+			(packet.peer_id != [1; 32]).then(|| addressed_packet([3; 32]))
+		});
+		assert!(space);
+		assert_eq!(queue.len(), 2);
+		assert!(!queue.contains_real_packets());
+		assert!(queue.iter().all(|packet| packet.peer_id == [3; 32]));
+
+		// Cover packets are left alone
+		queue.push(addressed_packet([0; 32]));
+		assert!(!queue.replace_real_with_cover(|_| Some(addressed_packet([4; 32]))));
+		assert_eq!(queue.iter().filter(|packet| packet.peer_id == [4; 32]).count(), 1);
+	}
+
+	#[test]
+	fn authored_packet_queue_replace_message_with_cover() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 4,
+			multiple_messages: true,
+		});
+		queue.push_with_message_id(addressed_packet([0; 32]), &[1; 16], 0);
+		queue.push_with_message_id(addressed_packet([1; 32]), &[2; 16], 0);
+		queue.push_with_message_id(addressed_packet([2; 32]), &[1; 16], 1);
+		queue.push(addressed_packet([3; 32]));
+
+		let (num_replaced, space) = queue.replace_message_with_cover(&[1; 16], |packet| {
+			(packet.peer_id != [2; 32]).then(|| addressed_packet([4; 32]))
+		});
+		assert_eq!(num_replaced, 1);
+		assert!(space);
+		assert!(!queue.contains_message(&[1; 16]));
+		assert!(queue.contains_message(&[2; 16]));
+		let peer_ids: Vec<_> = queue.iter().map(|packet| packet.peer_id[0]).collect();
+		assert_eq!(peer_ids, [4, 1, 3]);
+		assert!(queue.contains_real_packets());
+	}
+
+	#[test]
+	fn authored_packet_queue_deduplicate() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 6,
+			multiple_messages: true,
+		});
+		queue.push_with_message_id(addressed_packet([0; 32]), &[1; 16], 0);
+		queue.push_with_message_id(addressed_packet([1; 32]), &[1; 16], 1);
+		queue.push(addressed_packet([2; 32]));
+		queue.push(addressed_packet([3; 32]));
+		queue.push_with_message_id(addressed_packet([4; 32]), &[1; 16], 0);
+		queue.push_with_message_id(addressed_packet([5; 32]), &[2; 16], 0);
+
+		assert_eq!(queue.deduplicate(), 1);
+		let peer_ids: Vec<_> = queue.iter().map(|packet| packet.peer_id[0]).collect();
+		assert_eq!(peer_ids, [0, 1, 2, 3, 5]);
+		assert_eq!(queue.deduplicate(), 0);
+	}
+
+	#[test]
+	fn authored_packet_queue_reservation() {
+		let config = AuthoredPacketQueueConfig { capacity: 4, multiple_messages: true };
+		let mut queue = AuthoredPacketQueue::new(config);
+		let mut reservation = queue.reserve(3).ok().unwrap();
+		assert!(queue.check_space(1).is_ok());
+		assert!(matches!(queue.check_space(2), Err(CheckSpaceErr::Len)));
+		assert!(queue.reserve(2).is_err());
+
+		// Reservations only work with the queue they were made by
+		assert!(!AuthoredPacketQueue::new(config).use_reservation(&mut reservation, 2));
+		assert_eq!(reservation.count(), 3);
+
+		assert!(queue.use_reservation(&mut reservation, 2));
+		assert_eq!(reservation.count(), 1);
+		assert!(queue.check_space(3).is_ok());
+		assert!(queue.check_space(4).is_err());
+		assert!(queue.check_space_with_reservation(4, Some(&reservation)).is_ok());
+		assert!(queue.check_space_with_reservation(4, None).is_err());
+
+		drop(reservation);
+		assert!(queue.check_space(4).is_ok());
+	}
+
+	#[test]
+	fn authored_packet_queue_remove_enqueued_before() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 3,
+			multiple_messages: true,
+		});
+		queue.push(addressed_packet([0; 32]));
+		queue.push(addressed_packet([1; 32]));
+		let cutoff = Instant::now();
+		queue.push(addressed_packet([2; 32]));
+
+		assert_eq!(queue.remove_enqueued_before(cutoff), [[0; 32], [1; 32]]);
+		assert_eq!(queue.len(), 1);
+		assert!(queue.remove_enqueued_before(cutoff).is_empty());
+	}
+
+	#[test]
+	fn addressed_packet_to_bytes() {
+		let bytes = addressed_packet([1; 32]).to_bytes();
+		assert_eq!(bytes.len(), PEER_ID_SIZE + PACKET_SIZE);
+		assert_eq!(bytes[..PEER_ID_SIZE], [1; 32]);
+		assert!(bytes[PEER_ID_SIZE..].iter().all(|byte| *byte == 0));
+	}
+
+	#[test]
+	fn authored_packet_queue_move_message_to_front() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 5,
+			multiple_messages: true,
+		});
+		queue.push(addressed_packet([0; 32]));
+		queue.push_with_message_id(addressed_packet([1; 32]), &[1; 16], 1);
+		queue.push_with_message_id(addressed_packet([2; 32]), &[2; 16], 0);
+		queue.push_with_message_id(addressed_packet([3; 32]), &[1; 16], 0);
+		queue.push(addressed_packet([4; 32]));
+
+		assert_eq!(queue.move_message_to_front(&[1; 16]), 2);
+		let peer_ids: Vec<_> = queue.iter().map(|packet| packet.peer_id[0]).collect();
+		assert_eq!(peer_ids, [3, 1, 0, 2, 4]);
+		assert_eq!(queue.move_message_to_front(&[3; 16]), 0);
+		assert_eq!(queue.len(), 5);
+	}
+
+	#[test]
+	fn authored_packet_queue_remove_for_peer() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 3,
+			multiple_messages: true,
+		});
+		queue.push(addressed_packet([0; 32]));
+		queue.push(addressed_packet([1; 32]));
+		queue.push(addressed_packet([0; 32]));
+		assert_eq!(queue.remove_for_peer(&[0; 32]), 2);
+		assert_eq!(queue.remove_for_peer(&[0; 32]), 0);
+		assert_eq!(queue.iter().next().unwrap().peer_id, [1; 32]);
+	}
+
+	#[test]
+	fn authored_packet_queue_set_capacity() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 3,
+			multiple_messages: true,
+		});
+		for i in 0..3 {
+			queue.push(addressed_packet([i; 32]));
+		}
+		assert_eq!(queue.set_capacity(3), 0);
+		assert_eq!(queue.set_capacity(1), 2);
+		assert_eq!(queue.iter().next().unwrap().peer_id, [2; 32]);
+		assert_eq!(queue.set_capacity(5), 0);
+		assert!(queue.check_space(4).is_ok());
+	}
+
+	#[test]
+	fn authored_packet_queue_pop_first() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 3,
+			multiple_messages: true,
+		});
+		queue.push(addressed_packet([0; 32]));
+		queue.push(addressed_packet([1; 32]));
+		let (packet, space) = queue.pop_first_tagged(|packet| packet.peer_id == [1; 32]);
+		assert_eq!(packet.unwrap().0.peer_id, [1; 32]);
+		assert!(space);
+		assert!(matches!(
+			queue.pop_first_tagged(|packet| packet.peer_id == [2; 32]),
+			(None, false)
+		));
+		assert_eq!(queue.len(), 1);
+	}
+
+	#[test]
+	fn authored_packet_queue_pop_first_real() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 3,
+			multiple_messages: true,
+		});
+		queue.push(addressed_packet([0; 32]));
+		queue.replace_real_with_cover(|_| Some(addressed_packet([2; 32])));
+		queue.push(addressed_packet([1; 32]));
+		let (packet, space) = queue.pop_first_real();
+		assert_eq!(packet.unwrap().peer_id, [1; 32]);
+		assert!(space);
+		assert!(matches!(queue.pop_first_real(), (None, false)));
+		assert_eq!(queue.len(), 1);
+	}
+
+	#[test]
+	fn authored_packet_queue_pop_if_enqueued_before() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 2,
+			multiple_messages: true,
+		});
+		let before = Instant::now();
+		queue.push(addressed_packet([0; 32]));
+		assert!(matches!(queue.pop_if_enqueued_before(before), (None, false)));
+		let (packet, space) = queue.pop_if_enqueued_before(Instant::now() + Duration::from_secs(1));
+		assert_eq!(packet.unwrap().peer_id, [0; 32]);
+		assert!(space);
+	}
+
+	#[test]
+	fn authored_packet_queue_fill_notifier() {
+		struct Flag(AtomicUsize);
+		impl std::task::Wake for Flag {
+			fn wake(self: Arc<Self>) {
+				self.0.fetch_add(1, AtomicOrdering::Relaxed);
+			}
+		}
+		let flag = Arc::new(Flag(AtomicUsize::new(0)));
+		let waker = Waker::from(flag.clone());
+		let mut cx = Context::from_waker(&waker);
+
+		let notifier = FillNotifier::default();
+		let mut queue = AuthoredPacketQueue::with_fill_notifier(
+			AuthoredPacketQueueConfig { capacity: 2, multiple_messages: true },
+			notifier.clone(),
+		);
+		let mut token = notifier.token();
+		assert!(Pin::new(&mut token).poll(&mut cx).is_pending());
+		assert!(Pin::new(&mut token).poll(&mut cx).is_pending());
+		queue.push(addressed_packet([0; 32]));
+		assert_eq!(flag.0.load(AtomicOrdering::Relaxed), 1);
+		assert!(Pin::new(&mut token).poll(&mut cx).is_ready());
+
+		// Tokens only resolve on pushes after they are created
+		let mut token = notifier.token();
+		assert!(Pin::new(&mut token).poll(&mut cx).is_pending());
+	}
+
+	#[test]
+	fn authored_packet_queue_pop_first_tagged() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 3,
+			multiple_messages: true,
+		});
+		queue.push_tagged(addressed_packet([0; 32]), &[0; 16], 0, 7);
+		queue.push(addressed_packet([1; 32]));
+		let (packet, _) = queue.pop_first_tagged(|_| true);
+		assert_eq!(packet.map(|(packet, tag)| (packet.peer_id, tag)), Some(([0; 32], 7)));
+		let (packet, _) = queue.pop_first_tagged(|_| true);
+		assert_eq!(packet.map(|(packet, tag)| (packet.peer_id, tag)), Some(([1; 32], 0)));
+	}
+
+	#[test]
+	fn authored_packet_queue_drop_oldest_to_fit() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 3,
+			multiple_messages: true,
+		});
+		queue.push(addressed_packet([0; 32]));
+		queue.push(addressed_packet([1; 32]));
+		queue.push(addressed_packet([2; 32]));
+
+		assert_eq!(queue.drop_oldest_to_fit(4), 0);
+		assert_eq!(queue.drop_oldest_to_fit(2), 2);
+		assert_eq!(queue.len(), 1);
+		assert_eq!(queue.iter().next().unwrap().peer_id, [2; 32]);
+		assert_eq!(queue.drop_oldest_to_fit(2), 0);
+
+		// Reservations alone may prevent the packets from fitting
+		let _reservation = queue.reserve(1).ok().unwrap();
+		assert_eq!(queue.drop_oldest_to_fit(3), 1);
+		assert_eq!(queue.len(), 0);
+		assert!(queue.check_space(3).is_err());
+		assert_eq!(queue.drop_oldest_to_fit(3), 0);
+	}
+
+	#[cfg(feature = "scale")]
+	#[test]
+	fn authored_packet_queue_persistence_round_trip() {
+		let config = AuthoredPacketQueueConfig { capacity: 4, multiple_messages: true };
+		let mut queue = AuthoredPacketQueue::new(config);
+		queue.push_tagged(addressed_packet([0; 32]), &[1; 16], 0, 7);
+		queue.push_tagged(addressed_packet([1; 32]), &[1; 16], 1, 8);
+		queue.push_tagged(addressed_packet([2; 32]), &[1; 16], 1, 9);
+		let bytes = queue.encode();
+
+		let mut restored = AuthoredPacketQueue::new(config);
+		assert_eq!(restored.decode_and_push(&bytes).unwrap(), 3);
+		assert!(restored.contains_message(&[1; 16]));
+		// Fragment indices are restored; the last packet duplicates the second
+		assert_eq!(restored.deduplicate(), 1);
+		let (packet, _) = restored.pop_first_tagged(|_| true);
+		assert_eq!(packet.map(|(packet, tag)| (packet.peer_id, tag)), Some(([0; 32], 7)));
+		let (packet, _) = restored.pop_first_tagged(|_| true);
+		assert_eq!(packet.map(|(packet, tag)| (packet.peer_id, tag)), Some(([1; 32], 8)));
+
+		// Reserved space is not used
+		let _reservation = restored.reserve(2).ok().unwrap();
+		assert!(matches!(
+			restored.decode_and_push(&bytes),
+			Err(PersistenceErr::NotEnoughSpaceInQueue)
+		));
+		assert_eq!(restored.len(), 0);
+		assert!(matches!(restored.decode_and_push(&[1, 2, 3]), Err(PersistenceErr::Decode(_))));
+	}
+
+	#[test]
+	fn authored_packet_queue_plan_eviction() {
+		let mut queue = AuthoredPacketQueue::new(AuthoredPacketQueueConfig {
+			capacity: 3,
+			multiple_messages: true,
+		});
+		queue.push(addressed_packet([0; 32]));
+		queue.push(addressed_packet([1; 32]));
+		queue.push(addressed_packet([2; 32]));
+
+		assert!(matches!(
+			queue.plan_eviction(1, None, &NoEviction),
+			Err(CheckSpaceErr::Len)
+		));
+		assert_eq!(queue.len(), 3);
+		let plan = queue.plan_eviction(2, None, &EvictOldest).ok().unwrap();
+		assert_eq!(queue.len(), 3);
+		assert_eq!(queue.evict(plan), 2);
+		assert_eq!(queue.iter().next().unwrap().peer_id, [2; 32]);
+		assert!(matches!(
+			queue.plan_eviction(4, None, &EvictOldest),
+			Err(CheckSpaceErr::Capacity)
+		));
+
+		// Evict the second packet, then an out-of-range index
+		struct EvictSecondThenBad;
+		impl AuthoredQueueEvictionPolicy for EvictSecondThenBad {
+			fn should_evict(&self, queue: &[&AddressedPacket]) -> Option<usize> {
+				Some(if queue.len() == 3 { 1 } else { 5 })
+			}
+		}
+		queue.push(addressed_packet([3; 32]));
+		queue.push(addressed_packet([4; 32]));
+		assert!(matches!(
+			queue.plan_eviction(2, None, &EvictSecondThenBad),
+			Err(CheckSpaceErr::Len)
+		));
+		assert_eq!(queue.len(), 3);
+	}
+
+	#[test]
+	fn forward_packet_queue_reschedule() {
+		let now = Instant::now();
+		let mut queue = ForwardPacketQueue::new(3);
+		queue.insert(now, addressed_packet([0; 32]));
+		queue.insert(now + Duration::from_secs(1), addressed_packet([1; 32]));
+		queue.insert(now + Duration::from_secs(2), addressed_packet([0; 32]));
+
+		assert_eq!(queue.reschedule(&[0; 32], Duration::from_secs(5)), 2);
+		assert_eq!(queue.reschedule(&[2; 32], Duration::from_secs(5)), 0);
+
+		assert_eq!(queue.next_deadline(), Some(now + Duration::from_secs(1)));
+		assert_eq!(queue.pop().unwrap().peer_id, [1; 32]);
+		assert_eq!(queue.next_deadline(), Some(now + Duration::from_secs(5)));
+		assert_eq!(queue.pop().unwrap().peer_id, [0; 32]);
+		assert_eq!(queue.next_deadline(), Some(now + Duration::from_secs(7)));
+	}
 }
@@ -0,0 +1,95 @@
+// Copyright 2022 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Writing packets to byte streams.
+
+use super::sphinx::PACKET_SIZE;
+use std::{
+	cmp::min,
+	io::{self, Write},
+};
+
+/// Wraps a writer, limiting the total number of bytes written through it to [`PACKET_SIZE`].
+/// Writes beyond this limit are truncated. Call [`finish`](Self::finish) after writing to check
+/// that exactly [`PACKET_SIZE`] bytes were written.
+pub struct PacketWriter<W> {
+	inner: W,
+	written: usize,
+}
+
+impl<W: Write> PacketWriter<W> {
+	/// Create a new `PacketWriter` wrapping `inner`.
+	pub fn new(inner: W) -> Self {
+		Self { inner, written: 0 }
+	}
+
+	/// Returns the number of bytes that can still be written.
+	pub fn remaining(&self) -> usize {
+		PACKET_SIZE - self.written
+	}
+
+	/// Returns the inner writer. Fails if fewer than [`PACKET_SIZE`] bytes were written.
+	pub fn finish(self) -> io::Result<W> {
+		if self.remaining() == 0 {
+			Ok(self.inner)
+		} else {
+			Err(io::Error::new(
+				io::ErrorKind::UnexpectedEof,
+				format!("Packet incomplete; {} bytes missing", self.remaining()),
+			))
+		}
+	}
+}
+
+impl<W: Write> Write for PacketWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		// Returns Ok(0) if the packet is complete; write_all() turns this into an error
+		let len = min(buf.len(), self.remaining());
+		let written = self.inner.write(&buf[..len])?;
+		self.written += written;
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn packet_writer() {
+		let mut writer = PacketWriter::new(Vec::new());
+		writer.write_all(&[1; PACKET_SIZE - 1]).unwrap();
+		assert_eq!(writer.remaining(), 1);
+		assert_eq!(writer.write(&[2, 3]).unwrap(), 1);
+		assert_eq!(writer.write(&[4]).unwrap(), 0);
+		assert_eq!(writer.write_all(&[4]).unwrap_err().kind(), io::ErrorKind::WriteZero);
+		let data = writer.finish().unwrap();
+		assert_eq!(data.len(), PACKET_SIZE);
+		assert_eq!(data[PACKET_SIZE - 1], 2);
+
+		let mut writer = PacketWriter::new(Vec::new());
+		writer.write_all(&[1; 10]).unwrap();
+		assert_eq!(writer.finish().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+	}
+}
@@ -59,11 +59,13 @@ pub struct ReplayFilter {
 	key: [u8; 32],
 	/// Allocated on demand.
 	words: Option<Box<[u64; NUM_WORDS]>>,
+	/// Number of tags inserted so far.
+	num_inserts: u64,
 }
 
 impl ReplayFilter {
 	fn new_with_key(key: [u8; 32]) -> Self {
-		Self { key, words: None }
+		Self { key, words: None, num_inserts: 0 }
 	}
 
 	pub fn new(rng: &mut (impl Rng + CryptoRng)) -> Self {
@@ -90,6 +92,7 @@ impl ReplayFilter {
 			words[((i as usize) >> 6) % NUM_WORDS] |= 1 << (i & 63);
 			i = i.wrapping_add(tag.inc);
 		}
+		self.num_inserts += 1;
 	}
 
 	pub fn contains(&self, tag: ReplayTag) -> bool {
@@ -107,6 +110,14 @@ impl ReplayFilter {
 			},
 		}
 	}
+
+	/// Returns the theoretical false positive rate of [`contains`](Self::contains), given the
+	/// number of tags inserted so far.
+	pub fn false_positive_rate(&self) -> f64 {
+		let k = NUM_TAG_BITS as f64;
+		let bit_set_prob = 1.0 - (-k * (self.num_inserts as f64) / (NUM_BITS as f64)).exp();
+		bit_set_prob.powi(NUM_TAG_BITS as i32)
+	}
 }
 
 #[cfg(test)]
@@ -130,11 +141,13 @@ mod tests {
 	#[test]
 	fn false_positive_rate() {
 		let mut rf = ReplayFilter::new_with_key(Default::default());
+		assert_eq!(rf.false_positive_rate(), 0.0);
 
 		let mut rng = rand_xoshiro::Xoshiro256StarStar::seed_from_u64(0);
 		for _ in 0..3_000_000 {
 			rf.insert(rng.gen());
 		}
+		assert!((rf.false_positive_rate() - (1.0 / 15_000.0)).abs() < 0.000_005);
 
 		{
 			let mut rng = rand_xoshiro::Xoshiro256StarStar::seed_from_u64(0);
@@ -61,21 +61,25 @@ impl<'topology, X> RequestBuilder<'topology, X> {
 		self.destination_index
 	}
 
+	/// Build a request packet. If `first_index` is not [`None`], the packet will be sent to the
+	/// mixnode with this index first. It must not be the destination or the local node.
 	pub fn build_packet<R: Rng + CryptoRng>(
 		&self,
 		rng: &mut R,
 		write_payload_data: impl FnOnce(&mut PayloadData, &mut R) -> Result<(), TopologyErr>,
 		num_hops: usize,
+		first_index: Option<MixnodeIndex>,
 	) -> Result<(AddressedPacket, RouteMetrics), TopologyErr> {
 		// Generate route
 		let mut targets = ArrayVec::new();
 		let mut their_kx_publics = ArrayVec::new();
-		let first_mixnode_index = self.route_generator.gen_route(
+		let first_mixnode_index = self.route_generator.gen_route_via(
 			&mut targets,
 			&mut their_kx_publics,
 			rng,
 			RouteKind::ToMixnode(self.destination_index),
 			num_hops,
+			first_index,
 		)?;
 		let peer_id =
 			self.route_generator.topology().mixnode_index_to_peer_id(first_mixnode_index)?;
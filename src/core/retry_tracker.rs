@@ -0,0 +1,180 @@
+// Copyright 2022 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Tracking of requests which should be re-posted if no reply arrives in time.
+
+use super::{fragment::MessageId, sessions::SessionIndex, sphinx::MixnodeIndex};
+use std::{
+	collections::HashMap,
+	time::{Duration, Instant},
+};
+
+/// A request which may be re-posted.
+pub struct TrackedRequest {
+	pub session_index: SessionIndex,
+	pub destination_index: MixnodeIndex,
+	pub data: Vec<u8>,
+	pub num_surbs: usize,
+	retry_budget: Duration,
+	deadline: Instant,
+	num_retries: usize,
+}
+
+/// Keeps track of requests posted with
+/// [`post_request_with_reliability`](super::Mixnet::post_request_with_reliability) until a reply
+/// is received, the retries are exhausted, or the session ends.
+pub struct RetryTracker {
+	capacity: usize,
+	requests: HashMap<MessageId, TrackedRequest>,
+}
+
+impl RetryTracker {
+	/// Create a tracker which remembers at most `capacity` requests.
+	pub fn new(capacity: usize) -> Self {
+		Self { capacity, requests: HashMap::new() }
+	}
+
+	/// Start tracking a request. Any existing request with the same message ID is replaced. If
+	/// the tracker is full, the request with the earliest retry deadline is forgotten to make
+	/// room.
+	pub fn insert(
+		&mut self,
+		message_id: MessageId,
+		session_index: SessionIndex,
+		destination_index: MixnodeIndex,
+		data: Vec<u8>,
+		num_surbs: usize,
+		retry_budget: Duration,
+	) {
+		if self.capacity == 0 {
+			return
+		}
+		if !self.requests.contains_key(&message_id) && (self.requests.len() >= self.capacity) {
+			let earliest = self
+				.requests
+				.iter()
+				.min_by_key(|(_, request)| request.deadline)
+				.map(|(message_id, _)| *message_id);
+			if let Some(earliest) = earliest {
+				self.requests.remove(&earliest);
+			}
+		}
+		let request = TrackedRequest {
+			session_index,
+			destination_index,
+			data,
+			num_surbs,
+			retry_budget,
+			deadline: Instant::now() + retry_budget,
+			num_retries: 0,
+		};
+		self.requests.insert(message_id, request);
+	}
+
+	/// Stop tracking the request with the given message ID, because a reply has been received,
+	/// re-posting failed, or the request has been cancelled. Returns `true` if the request was
+	/// being tracked.
+	pub fn remove(&mut self, message_id: &MessageId) -> bool {
+		self.requests.remove(message_id).is_some()
+	}
+
+	/// Stop tracking all requests for sessions before `session_index`.
+	pub fn remove_sessions_before(&mut self, session_index: SessionIndex) {
+		self.requests.retain(|_, request| request.session_index >= session_index);
+	}
+
+	/// If the request with the given message ID is being tracked and its retry deadline has
+	/// passed, returns it. The deadline is pushed back by the retry budget, on the assumption that
+	/// the caller is about to re-post the request. If the request has already been retried
+	/// `max_retries` times, it is forgotten instead, and [`None`] is returned.
+	pub fn timed_out(
+		&mut self,
+		message_id: &MessageId,
+		now: Instant,
+		max_retries: usize,
+	) -> Option<&TrackedRequest> {
+		let request = self.requests.get(message_id)?;
+		if now < request.deadline {
+			return None
+		}
+		if request.num_retries >= max_retries {
+			self.requests.remove(message_id);
+			return None
+		}
+		let request = self.requests.get_mut(message_id).expect("Checked above");
+		request.num_retries += 1;
+		request.deadline = now + request.retry_budget;
+		Some(request)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn timed_out_respects_deadline() {
+		let mut tracker = RetryTracker::new(10);
+		let budget = Duration::from_secs(10);
+		let destination_index = 0usize.try_into().unwrap();
+		tracker.insert([1; 16], 0, destination_index, vec![1, 2, 3], 1, budget);
+		let now = Instant::now();
+		assert!(tracker.timed_out(&[1; 16], now, 5).is_none());
+		assert!(tracker.timed_out(&[2; 16], now + budget, 5).is_none());
+		assert_eq!(tracker.timed_out(&[1; 16], now + budget, 5).unwrap().data, [1, 2, 3]);
+		// Deadline has been pushed back
+		assert!(tracker.timed_out(&[1; 16], now + budget, 5).is_none());
+		assert!(tracker.remove(&[1; 16]));
+		assert!(!tracker.remove(&[1; 16]));
+	}
+
+	#[test]
+	fn retries_are_limited() {
+		let mut tracker = RetryTracker::new(10);
+		let destination_index = 0usize.try_into().unwrap();
+		tracker.insert([1; 16], 0, destination_index, vec![], 1, Duration::ZERO);
+		let now = Instant::now();
+		assert!(tracker.timed_out(&[1; 16], now, 2).is_some());
+		assert!(tracker.timed_out(&[1; 16], now, 2).is_some());
+		assert!(tracker.timed_out(&[1; 16], now, 2).is_none());
+		// Forgotten once the retries are exhausted
+		assert!(!tracker.remove(&[1; 16]));
+	}
+
+	#[test]
+	fn capacity_and_sessions() {
+		let mut tracker = RetryTracker::new(2);
+		let destination_index = 0usize.try_into().unwrap();
+		tracker.insert([1; 16], 1, destination_index, vec![], 1, Duration::from_secs(1));
+		tracker.insert([2; 16], 2, destination_index, vec![], 1, Duration::from_secs(3));
+		tracker.insert([3; 16], 3, destination_index, vec![], 1, Duration::from_secs(2));
+		// Request with the earliest deadline forgotten to make room
+		assert_eq!(tracker.requests.len(), 2);
+		assert!(!tracker.requests.contains_key(&[1; 16]));
+
+		tracker.remove_sessions_before(3);
+		assert!(!tracker.remove(&[2; 16]));
+		assert!(tracker.remove(&[3; 16]));
+
+		let mut tracker = RetryTracker::new(0);
+		tracker.insert([1; 16], 1, destination_index, vec![], 1, Duration::from_secs(1));
+		assert!(!tracker.remove(&[1; 16]));
+	}
+}
@@ -22,7 +22,7 @@
 
 use super::{
 	kx_pair::KxPair, packet_queues::AuthoredPacketQueue, replay_filter::ReplayFilter,
-	topology::Topology,
+	sphinx::KxPublic, topology::Topology,
 };
 use std::{
 	fmt,
@@ -39,6 +39,11 @@ pub struct Session<X> {
 	pub authored_packet_queue: AuthoredPacketQueue,
 	/// See [`SessionConfig`](super::config::SessionConfig::mean_authored_packet_period).
 	pub mean_authored_packet_period: Duration,
+	/// Known local clock skew. See [`Mixnet::authored_queue_clock_skew_tolerance`].
+	///
+	/// [`Mixnet::authored_queue_clock_skew_tolerance`]:
+	/// super::Mixnet::authored_queue_clock_skew_tolerance
+	pub clock_skew: Duration,
 	/// Filter applied to incoming packets to prevent replay. This is per-session because the
 	/// key-exchange keys are rotated every session. Note that while this always exists, for
 	/// sessions where we are not a mixnode, it should never contain anything, and so should not
@@ -84,6 +89,8 @@ impl Add<SessionIndex> for RelSessionIndex {
 	}
 }
 
+// There are only ever two of these, so the wasted space isn't a concern
+#[allow(clippy::large_enum_variant)]
 pub enum SessionSlot<X> {
 	Empty,
 	KxPair(KxPair),
@@ -92,11 +99,42 @@ pub enum SessionSlot<X> {
 	Full(Session<X>),
 }
 
+/// The state of a session slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionSlotKind {
+	/// Nothing is known about the session yet.
+	Empty,
+	/// A key-exchange key pair has been generated for the session, but the mixnodes are not yet
+	/// known.
+	KxPair,
+	/// The mixnet is disabled for the session.
+	Disabled,
+	/// The session is active.
+	Full,
+}
+
 impl<X> SessionSlot<X> {
+	pub fn kind(&self) -> SessionSlotKind {
+		match self {
+			Self::Empty => SessionSlotKind::Empty,
+			Self::KxPair(_) => SessionSlotKind::KxPair,
+			Self::Disabled => SessionSlotKind::Disabled,
+			Self::Full(_) => SessionSlotKind::Full,
+		}
+	}
+
 	pub fn is_empty(&self) -> bool {
 		matches!(self, Self::Empty)
 	}
 
+	pub fn kx_public(&self) -> Option<&KxPublic> {
+		match self {
+			Self::KxPair(kx_pair) => Some(kx_pair.public()),
+			Self::Full(session) => Some(session.kx_pair.public()),
+			_ => None,
+		}
+	}
+
 	pub fn as_option(&self) -> Option<&Session<X>> {
 		match self {
 			Self::Full(session) => Some(session),
@@ -157,7 +195,7 @@ impl<X> IndexMut<RelSessionIndex> for Sessions<X> {
 }
 
 /// Each session should progress through these phases in order.
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum SessionPhase {
 	/// Generate cover traffic to the current session's mixnode set.
 	CoverToCurrent,
@@ -173,6 +211,28 @@ pub enum SessionPhase {
 }
 
 impl SessionPhase {
+	/// Returns the phase following this one, or [`None`] if this is the last phase. After the
+	/// last phase, the session index should be incremented and the phase reset to
+	/// [`CoverToCurrent`](Self::CoverToCurrent).
+	pub fn next(self) -> Option<Self> {
+		match self {
+			Self::CoverToCurrent => Some(Self::RequestsToCurrent),
+			Self::RequestsToCurrent => Some(Self::CoverToPrev),
+			Self::CoverToPrev => Some(Self::DisconnectFromPrev),
+			Self::DisconnectFromPrev => None,
+		}
+	}
+
+	/// Returns the phase preceding this one, or [`None`] if this is the first phase.
+	pub fn prev(self) -> Option<Self> {
+		match self {
+			Self::CoverToCurrent => None,
+			Self::RequestsToCurrent => Some(Self::CoverToCurrent),
+			Self::CoverToPrev => Some(Self::RequestsToCurrent),
+			Self::DisconnectFromPrev => Some(Self::CoverToPrev),
+		}
+	}
+
 	/// Is the previous session still needed?
 	pub fn need_prev(self) -> bool {
 		self < Self::DisconnectFromPrev
@@ -187,6 +247,15 @@ impl SessionPhase {
 		}
 	}
 
+	/// Should we forward packets received in the specified session? Note that packets should
+	/// additionally only be forwarded in sessions where the local node is a mixnode.
+	pub fn gen_forward_packets(self, rel_session_index: RelSessionIndex) -> bool {
+		match rel_session_index {
+			RelSessionIndex::Prev => self.need_prev(),
+			RelSessionIndex::Current => true,
+		}
+	}
+
 	/// Which session should requests be built for by default?
 	pub fn default_request_session(self) -> RelSessionIndex {
 		if self >= Self::RequestsToCurrent {
@@ -34,6 +34,8 @@ pub type DelaySeed = [u8; DELAY_SEED_SIZE];
 
 /// Unitless delay. Can be converted to a [`Duration`] with [`to_duration`](Self::to_duration).
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "f64"))]
 pub struct Delay(f64);
 
 impl Delay {
@@ -42,6 +44,14 @@ impl Delay {
 		Self(0.0)
 	}
 
+	/// Returns the expected total delay for a packet which is forwarded `hops` times, with a mean
+	/// forwarding delay of `mean`. The delay is relative to a unit of one second; convert it to a
+	/// [`Duration`] with [`to_duration(Duration::from_secs(1))`](Self::to_duration). Intended
+	/// for tests.
+	pub fn from_hops(hops: u8, mean: Duration) -> Self {
+		Self(f64::from(hops) * mean.as_secs_f64())
+	}
+
 	/// Returns a random delay sampled from an exponential distribution with mean 1. `seed`
 	/// provides the entropy.
 	pub fn exp(seed: &DelaySeed) -> Self {
@@ -58,6 +68,12 @@ impl Delay {
 		Self(delay.min(10.0))
 	}
 
+	/// Convert a [`Duration`] into a unitless delay by dividing by `unit`. This is the inverse of
+	/// [`to_duration`](Self::to_duration), up to rounding. Returns [`None`] if `unit` is zero.
+	pub fn from_duration(duration: Duration, unit: Duration) -> Option<Self> {
+		(duration.as_secs_f64() / unit.as_secs_f64()).try_into().ok()
+	}
+
 	/// Convert the unitless delay into a [`Duration`] by multiplying by `unit`. For delays
 	/// calculated by different parties to match, they must all agree on `unit`!
 	pub fn to_duration(self, unit: Duration) -> Duration {
@@ -75,6 +91,20 @@ impl Ord for Delay {
 	}
 }
 
+impl TryFrom<f64> for Delay {
+	type Error = &'static str;
+
+	fn try_from(delay: f64) -> Result<Self, Self::Error> {
+		// Negative or infinite delays would cause panics in to_duration(), and NaN delays would
+		// cause panics in cmp()
+		if delay.is_finite() && (delay >= 0.0) {
+			Ok(Self(delay))
+		} else {
+			Err("Delay must be finite and non-negative")
+		}
+	}
+}
+
 impl Add for Delay {
 	type Output = Self;
 
@@ -93,6 +123,23 @@ impl AddAssign for Delay {
 mod tests {
 	use super::*;
 
+	#[test]
+	fn from_duration() {
+		let unit = Duration::from_millis(200);
+		let delay = Delay::from_duration(Duration::from_millis(500), unit).unwrap();
+		assert_eq!(delay, Delay(2.5));
+		assert_eq!(delay.to_duration(unit), Duration::from_millis(500));
+		assert_eq!(Delay::from_duration(Duration::from_secs(1), Duration::ZERO), None);
+	}
+
+	#[test]
+	fn from_hops() {
+		let delay = Delay::from_hops(3, Duration::from_millis(500));
+		assert_eq!(delay, Delay(1.5));
+		assert_eq!(delay.to_duration(Duration::from_secs(1)), Duration::from_millis(1500));
+		assert_eq!(Delay::from_hops(0, Duration::from_secs(1)), Delay::zero());
+	}
+
 	#[test]
 	fn portable_deterministic_exp() {
 		assert_eq!(
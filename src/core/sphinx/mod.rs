@@ -33,9 +33,9 @@ pub use self::{
 	crypto::{derive_kx_public, derive_kx_shared_secret, gen_kx_secret, KxSecret, SharedSecret},
 	delay::Delay,
 	packet::{
-		CoverId, KxPublic, Packet, Payload, PayloadData, PeerId, RawMixnodeIndex, SurbId,
-		COVER_ID_SIZE, KX_PUBLIC_SIZE, MAX_HOPS, MAX_MIXNODE_INDEX, PACKET_SIZE, PAYLOAD_DATA_SIZE,
-		PAYLOAD_SIZE, PEER_ID_SIZE, SURB_ID_SIZE,
+		KxPublic, Packet, PayloadData, PeerId, RawMixnodeIndex, SurbId, KX_PUBLIC_SIZE, MAX_HOPS,
+		MAX_MIXNODE_INDEX, PACKET_SIZE, PAYLOAD_DATA_SIZE, PAYLOAD_SIZE, PEER_ID_SIZE,
+		SURB_ID_SIZE,
 	},
 	peel::*,
 	target::{MixnodeIndex, Target},
@@ -71,9 +71,12 @@ pub type PeerId = [u8; PEER_ID_SIZE];
 /// invariance at each hop.
 pub const MAX_ACTIONS_PAD_SIZE: usize = RAW_ACTION_SIZE + PEER_ID_SIZE + MAC_SIZE;
 pub const SURB_COVER_ID_SIZE: usize = 16;
+/// Size in bytes of a [`SurbId`].
 pub const SURB_ID_SIZE: usize = SURB_COVER_ID_SIZE;
+/// Identifies a SURB. Included in the header of reply packets.
 pub type SurbId = [u8; SURB_ID_SIZE];
 pub const COVER_ID_SIZE: usize = SURB_COVER_ID_SIZE;
+/// Identifies a cover packet. Included in the header of some cover packets.
 pub type CoverId = [u8; COVER_ID_SIZE];
 pub const ACTIONS_SIZE: usize = (MAX_HOPS * (RAW_ACTION_SIZE + MAC_SIZE)) +
 	PEER_ID_SIZE + // Allow one hop to use a peer ID
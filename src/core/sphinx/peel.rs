@@ -33,14 +33,25 @@ pub fn kx_public(packet: &Packet) -> &KxPublic {
 #[derive(Debug, PartialEq, Eq)]
 pub enum Action {
 	/// The packet in `out` should be forwarded to `target` after `delay`.
-	ForwardTo { target: Target, delay: Delay },
+	ForwardTo {
+		/// Where the packet should be forwarded to.
+		target: Target,
+		/// How long to hold the packet before forwarding it.
+		delay: Delay,
+	},
 	/// The payload data in `out[..PAYLOAD_DATA_SIZE]` should be delivered locally.
 	DeliverRequest,
 	/// The reply payload in `out[..PAYLOAD_SIZE]` should be decrypted according to `surb_id` and
 	/// then delivered locally.
-	DeliverReply { surb_id: SurbId },
+	DeliverReply {
+		/// Identifies the SURB used to send the reply.
+		surb_id: SurbId,
+	},
 	/// The packet was a cover packet with the specified ID. There is no payload.
-	DeliverCover { cover_id: Option<CoverId> },
+	DeliverCover {
+		/// The cover ID, if any.
+		cover_id: Option<CoverId>,
+	},
 }
 
 #[derive(Debug, thiserror::Error, PartialEq, Eq)]
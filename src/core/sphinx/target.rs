@@ -61,8 +61,11 @@ impl fmt::Display for MixnodeIndex {
 	}
 }
 
+/// The target of a hop.
 #[derive(Debug, PartialEq, Eq)]
 pub enum Target {
+	/// The mixnode with the given index in the session's mixnode list.
 	MixnodeIndex(MixnodeIndex),
+	/// The node with the given peer ID. Only used for the final hop to a non-mixnode.
 	PeerId(PeerId),
 }
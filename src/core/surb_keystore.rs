@@ -25,7 +25,7 @@ use super::{
 	sphinx::{SurbId, SurbPayloadEncryptionKeys, SURB_ID_SIZE},
 };
 use hashlink::{linked_hash_map, LinkedHashMap};
-use log::debug;
+use log::warn;
 use rand::{CryptoRng, Rng};
 
 struct Value {
@@ -62,6 +62,24 @@ impl SurbKeystore {
 		Self { capacity, surbs: LinkedHashMap::with_capacity(capacity) }
 	}
 
+	pub fn len(&self) -> usize {
+		self.surbs.len()
+	}
+
+	/// Evict the oldest entries until there is room for at least one more. A warning is logged for
+	/// each evicted entry; eviction means a reply to the corresponding request will be dropped on
+	/// arrival.
+	pub fn trim_to_capacity(&mut self, log_target: &str) {
+		while self.surbs.len() >= self.capacity {
+			let Some((id, value)) = self.surbs.pop_front() else { break };
+			warn!(
+				target: log_target,
+				"Too many entries in SURB keystore; evicted SURB {id:x?} (request {:x?})",
+				value.message_id
+			);
+		}
+	}
+
 	/// Create an entry for a new SURB. Returns the randomly generated ID and a mutable reference
 	/// to the keys, which should be filled in by the caller.
 	pub fn insert(
@@ -72,10 +90,7 @@ impl SurbKeystore {
 	) -> (SurbId, &mut SurbPayloadEncryptionKeys) {
 		// Discard the oldest SURB if we're already at capacity
 		debug_assert!(self.surbs.len() <= self.capacity);
-		if self.surbs.len() == self.capacity {
-			debug!(target: log_target, "Too many entries in SURB keystore; evicting oldest");
-			self.surbs.pop_front();
-		}
+		self.trim_to_capacity(log_target);
 
 		let mut id = [0; SURB_ID_SIZE];
 		rng.fill_bytes(&mut id);
@@ -94,10 +109,27 @@ impl SurbKeystore {
 	}
 
 	/// Returns the entry for a SURB, or [`None`] if the ID is not recognised.
-	pub fn entry(&mut self, id: &SurbId) -> Option<Entry> {
+	pub fn entry(&mut self, id: &SurbId) -> Option<Entry<'_>> {
 		match self.surbs.entry(*id) {
 			linked_hash_map::Entry::Occupied(entry) => Some(Entry(entry)),
 			linked_hash_map::Entry::Vacant(_) => None,
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn evict_oldest() {
+		let mut rng = rand::thread_rng();
+		let mut keystore = SurbKeystore::new(2);
+		let first_id = keystore.insert(&mut rng, &[0; 16], "mixnet").0;
+		let second_id = keystore.insert(&mut rng, &[1; 16], "mixnet").0;
+		let third_id = keystore.insert(&mut rng, &[2; 16], "mixnet").0;
+		assert!(keystore.entry(&first_id).is_none());
+		assert_eq!(keystore.entry(&second_id).unwrap().message_id(), &[1; 16]);
+		assert_eq!(keystore.entry(&third_id).unwrap().message_id(), &[2; 16]);
+	}
+}
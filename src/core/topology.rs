@@ -25,18 +25,25 @@ use super::sphinx::{
 };
 use arrayvec::ArrayVec;
 use either::Either;
+use log::warn;
 use rand::{seq::SliceRandom, CryptoRng, Rng};
 use std::{
 	cmp::{max, min},
+	collections::HashSet,
 	fmt,
+	time::Duration,
 };
 
-/// Per-mixnode data.
+/// Per-mixnode data. With the `serde` feature, this can be serialized; the key-exchange public key
+/// and peer ID are hex-encoded.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Mixnode<X> {
 	/// Key-exchange public key for the mixnode.
+	#[cfg_attr(feature = "serde", serde(with = "hex"))]
 	pub kx_public: KxPublic,
 	/// Peer ID for the mixnode.
+	#[cfg_attr(feature = "serde", serde(with = "hex"))]
 	pub peer_id: PeerId,
 	/// Extra data; for use by the crate user.
 	pub extra: X,
@@ -59,19 +66,92 @@ pub enum TopologyErr {
 	/// There aren't enough mixnodes.
 	#[error("Too few mixnodes; this should have been caught earlier")]
 	TooFewMixnodes,
+	/// There are already as many mixnodes as can be indexed.
+	#[error("Too many mixnodes")]
+	TooManyMixnodes,
+	/// Multiple mixnodes have the same peer ID.
+	#[error("Duplicate peer ID {0:x?}")]
+	DuplicatePeerId(PeerId),
+	/// A route failed validation.
+	#[error("Invalid route: {reason}")]
+	InvalidRoute {
+		/// Why the route is invalid.
+		reason: &'static str,
+	},
+	/// There are fewer suitable mixnodes than requested.
+	#[error("Insufficient mixnodes (need {need}, have {have})")]
+	InsufficientMixnodes {
+		/// Number of mixnodes requested.
+		need: usize,
+		/// Number of suitable mixnodes available.
+		have: usize,
+	},
+	/// There are fewer gateway mixnodes than requested.
+	#[error("Insufficient gateway mixnodes (need {need}, have {have})")]
+	InsufficientGateways {
+		/// Number of gateway mixnodes requested.
+		need: usize,
+		/// Number of gateway mixnodes available.
+		have: usize,
+	},
 	/// The local node has not managed to connect to any gateway mixnodes.
 	#[error("The local node has not managed to connect to any gateway mixnodes")]
 	NoConnectedGatewayMixnodes,
+	/// A mixnode permutation is not a permutation of the mixnode indices.
+	#[error("Bad mixnode permutation")]
+	BadPermutation,
+	/// An out-of-range gateway mixnode index was supplied.
+	#[error("Bad gateway mixnode index ({0})")]
+	BadGatewayIndex(usize),
 }
 
+/// Error building a [`Topology`] from JSON.
+#[cfg(feature = "json")]
+#[derive(Debug, thiserror::Error)]
+pub enum JsonErr {
+	/// The JSON could not be parsed.
+	#[error("Bad JSON: {0}")]
+	Json(#[from] serde_json::Error),
+	/// There are more mixnodes than can be indexed.
+	#[error("Too many mixnodes ({0}, max {max})", max = MAX_MIXNODE_INDEX as usize + 1)]
+	TooManyMixnodes(usize),
+}
+
+/// Error building a [`Topology`] from SCALE-encoded data.
+#[cfg(feature = "scale")]
+#[derive(Debug, thiserror::Error)]
+pub enum ScaleErr {
+	/// The data could not be decoded.
+	#[error("Bad SCALE-encoded data: {0}")]
+	Decode(#[from] codec::Error),
+	/// There are more mixnodes than can be indexed.
+	#[error("Too many mixnodes ({0}, max {max})", max = MAX_MIXNODE_INDEX as usize + 1)]
+	TooManyMixnodes(usize),
+}
+
+/// JSON representation of a mixnode. Keys are hex-encoded.
+#[cfg(feature = "json")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonMixnode {
+	#[serde(with = "hex")]
+	kx_public: KxPublic,
+	#[serde(with = "hex")]
+	peer_id: PeerId,
+}
+
+/// The mixnodes for a session, plus the position of the local node relative to them.
 pub struct Topology<X> {
 	mixnodes: Vec<Mixnode<X>>,
 	local_kx_public: KxPublic,
 	local_node: LocalNode,
+	/// Number of gateway mixnodes to connect to when the local node is not a mixnode.
+	num_gateway_mixnodes: u32,
 }
 
 impl<X> Topology<X> {
-	/// `mixnodes` must be no longer than [`MAX_MIXNODE_INDEX + 1`](MAX_MIXNODE_INDEX).
+	/// Build a new topology. If the local node is not a mixnode, `num_gateway_mixnodes` gateway
+	/// mixnodes are chosen at random. `mixnodes` must be no longer than
+	/// [`MAX_MIXNODE_INDEX + 1`](MAX_MIXNODE_INDEX).
 	pub fn new(
 		rng: &mut impl Rng,
 		mixnodes: Vec<Mixnode<X>>,
@@ -121,13 +201,627 @@ impl<X> Topology<X> {
 				},
 			);
 
-		Self { mixnodes, local_kx_public: *local_kx_public, local_node }
+		Self { mixnodes, local_kx_public: *local_kx_public, local_node, num_gateway_mixnodes }
+	}
+
+	/// Like [`new`](Self::new), but deterministic, for reproducible tests. Mixnode `i` in the
+	/// built topology is `mixnodes[permutation[i]]`. If the local node is not a mixnode, it will
+	/// connect to the gateway mixnodes at `gateway_indices` (indices after permutation) instead of
+	/// randomly chosen ones. `gateway_indices` is ignored if the local node is a mixnode.
+	#[cfg(any(test, feature = "test-utils"))]
+	pub fn new_shuffled(
+		mixnodes: Vec<Mixnode<X>>,
+		permutation: Vec<usize>,
+		local_kx_public: &KxPublic,
+		gateway_indices: Vec<usize>,
+	) -> Result<Self, TopologyErr> {
+		if mixnodes.len() > (MAX_MIXNODE_INDEX + 1) as usize {
+			return Err(TopologyErr::TooManyMixnodes)
+		}
+		if permutation.len() != mixnodes.len() {
+			return Err(TopologyErr::BadPermutation)
+		}
+		let mut mixnodes: Vec<_> = mixnodes.into_iter().map(Some).collect();
+		let mixnodes = permutation
+			.into_iter()
+			.map(|index| mixnodes.get_mut(index).and_then(Option::take))
+			.collect::<Option<Vec<_>>>()
+			.ok_or(TopologyErr::BadPermutation)?;
+
+		let num_gateway_mixnodes = gateway_indices.len().try_into().unwrap_or(u32::MAX);
+		let local_index = mixnodes.iter().position(|mixnode| &mixnode.kx_public == local_kx_public);
+		let local_node = match local_index {
+			Some(index) =>
+				LocalNode::Mixnode(index.try_into().expect("Checked mixnodes.len() above")),
+			None => LocalNode::NonMixnode(
+				gateway_indices
+					.into_iter()
+					.map(|index| {
+						if index < mixnodes.len() {
+							Ok(index.try_into().expect("Checked mixnodes.len() above"))
+						} else {
+							Err(TopologyErr::BadGatewayIndex(index))
+						}
+					})
+					.collect::<Result<_, _>>()?,
+			),
+		};
+
+		Ok(Self { mixnodes, local_kx_public: *local_kx_public, local_node, num_gateway_mixnodes })
+	}
+
+	/// Build a simple topology for tests. The mixnodes are kept in the given order, forming a
+	/// ring in which mixnode `i` neighbours mixnodes `i - 1` and `i + 1` (modulo the number of
+	/// mixnodes). If the local node is not a mixnode, every mixnode is used as a gateway mixnode.
+	/// Note that routes are not restricted to follow the ring; as with any topology, packets may
+	/// hop between any two mixnodes. `mixnodes` must be no longer than
+	/// [`MAX_MIXNODE_INDEX + 1`](MAX_MIXNODE_INDEX).
+	#[cfg(any(test, feature = "test-utils"))]
+	pub fn new_ring(mixnodes: Vec<Mixnode<X>>, local_kx_public: &KxPublic) -> Self {
+		debug_assert!(mixnodes.len() <= (MAX_MIXNODE_INDEX + 1) as usize);
+		let index = |index: usize| -> MixnodeIndex {
+			index
+				.try_into()
+				.expect("Topology::new_ring() contract limits size of mixnode set")
+		};
+		let local_index = mixnodes.iter().position(|mixnode| &mixnode.kx_public == local_kx_public);
+		let local_node = match local_index {
+			Some(local_index) => LocalNode::Mixnode(index(local_index)),
+			None => LocalNode::NonMixnode((0..mixnodes.len()).map(index).collect()),
+		};
+		let num_gateway_mixnodes = mixnodes.len().try_into().unwrap_or(u32::MAX);
+		Self { mixnodes, local_kx_public: *local_kx_public, local_node, num_gateway_mixnodes }
+	}
+
+	/// Like [`new`](Self::new), but fails with [`TopologyErr::DuplicatePeerId`] if multiple
+	/// mixnodes have the same peer ID. A warning is logged for every duplicate, as duplicates
+	/// indicate a bug in the source of the mixnode list.
+	///
+	/// Duplicates are not simply removed, as this would change the indices of the following
+	/// mixnodes, and all nodes must agree on mixnode indices.
+	pub fn new_validate_unique_peer_ids(
+		rng: &mut impl Rng,
+		mixnodes: Vec<Mixnode<X>>,
+		local_kx_public: &KxPublic,
+		num_gateway_mixnodes: u32,
+		log_target: &str,
+	) -> Result<Self, TopologyErr> {
+		let mut peer_ids = HashSet::with_capacity(mixnodes.len());
+		let mut first_duplicate = None;
+		for (index, mixnode) in mixnodes.iter().enumerate() {
+			if !peer_ids.insert(mixnode.peer_id) {
+				warn!(
+					target: log_target,
+					"Mixnode {index} has the same peer ID as an earlier mixnode ({:x?})",
+					mixnode.peer_id
+				);
+				first_duplicate.get_or_insert(mixnode.peer_id);
+			}
+		}
+		match first_duplicate {
+			Some(peer_id) => Err(TopologyErr::DuplicatePeerId(peer_id)),
+			None => Ok(Self::new(rng, mixnodes, local_kx_public, num_gateway_mixnodes)),
+		}
+	}
+
+	/// Like [`new`](Self::new), but takes the mixnodes from an iterator, and fails with
+	/// [`TopologyErr::TooManyMixnodes`] if it yields more than
+	/// [`MAX_MIXNODE_INDEX + 1`](MAX_MIXNODE_INDEX) mixnodes.
+	pub fn new_from_mixnode_iter(
+		rng: &mut impl Rng,
+		mixnodes: impl IntoIterator<Item = Mixnode<X>>,
+		local_kx_public: &KxPublic,
+		num_gateway_mixnodes: u32,
+	) -> Result<Self, TopologyErr> {
+		let mut mixnodes = mixnodes.into_iter();
+		let max_mixnodes = (MAX_MIXNODE_INDEX + 1) as usize;
+		let collected: Vec<_> = mixnodes.by_ref().take(max_mixnodes).collect();
+		if mixnodes.next().is_some() {
+			return Err(TopologyErr::TooManyMixnodes)
+		}
+		Ok(Self::new(rng, collected, local_kx_public, num_gateway_mixnodes))
+	}
+
+	/// If the local node is not a mixnode and has fewer gateway mixnodes than it should, choose
+	/// some more at random.
+	fn fill_gateways(&mut self, rng: &mut impl Rng) {
+		let LocalNode::NonMixnode(gateway_indices) = &mut self.local_node else { return };
+		let Some(num_needed) =
+			(self.num_gateway_mixnodes as usize).checked_sub(gateway_indices.len())
+		else {
+			return
+		};
+		let candidates: Vec<MixnodeIndex> = (0..self.mixnodes.len())
+			.map(|index| {
+				index.try_into().expect("Topology::new() contract limits size of mixnode set")
+			})
+			.filter(|index| !gateway_indices.contains(index))
+			.collect();
+		let num_needed = min(num_needed, candidates.len());
+		gateway_indices.extend(
+			rand::seq::index::sample(rng, candidates.len(), num_needed)
+				.iter()
+				.map(|i| candidates[i]),
+		);
+	}
+
+	/// Change the number of gateway mixnodes to connect to when the local node is not a mixnode.
+	/// If the number is reduced, a random subset of the current gateway mixnodes is kept. If it is
+	/// increased, more gateway mixnodes are chosen at random. Gateway mixnodes are otherwise left
+	/// alone, to avoid unnecessary reconnections.
+	pub fn resize_gateway_set(&mut self, rng: &mut impl Rng, num_gateway_mixnodes: u32) {
+		self.num_gateway_mixnodes = num_gateway_mixnodes;
+		if let LocalNode::NonMixnode(gateway_indices) = &mut self.local_node {
+			if gateway_indices.len() > (num_gateway_mixnodes as usize) {
+				gateway_indices.shuffle(rng);
+				gateway_indices.truncate(num_gateway_mixnodes as usize);
+			}
+		}
+		self.fill_gateways(rng);
 	}
 
+	/// Add a mixnode to the end of the mixnode list, returning its index. If the new mixnode has
+	/// the local node's key-exchange public key, the local node becomes a mixnode. Otherwise, if
+	/// the local node is not a mixnode and is short of gateway mixnodes, more are chosen.
+	///
+	/// Mixnode indices are used in packet headers, so all nodes must agree on the topology. This
+	/// function should only be used to track a change that all nodes are aware of.
+	pub fn add_mixnode(
+		&mut self,
+		rng: &mut impl Rng,
+		mixnode: Mixnode<X>,
+	) -> Result<MixnodeIndex, TopologyErr> {
+		let index: MixnodeIndex =
+			self.mixnodes.len().try_into().map_err(|_| TopologyErr::TooManyMixnodes)?;
+		let is_local = mixnode.kx_public == self.local_kx_public;
+		self.mixnodes.push(mixnode);
+		if is_local && !self.is_mixnode() {
+			self.local_node = LocalNode::Mixnode(index);
+		} else {
+			self.fill_gateways(rng);
+		}
+		Ok(index)
+	}
+
+	/// Remove the mixnode with the given index, returning it. The indices of all following
+	/// mixnodes are decremented. If the removed mixnode was the local node, the local node stops
+	/// being a mixnode and gateway mixnodes are chosen. If the removed mixnode was a gateway
+	/// mixnode, a replacement is chosen.
+	///
+	/// As with [`add_mixnode`](Self::add_mixnode), all nodes must agree on the topology.
+	pub fn remove_mixnode(
+		&mut self,
+		rng: &mut impl Rng,
+		index: MixnodeIndex,
+	) -> Result<Mixnode<X>, TopologyErr> {
+		if (index.get() as usize) >= self.mixnodes.len() {
+			return Err(TopologyErr::BadMixnodeIndex(index))
+		}
+		let mixnode = self.mixnodes.remove(index.get() as usize);
+
+		let shift = |other: MixnodeIndex| {
+			if other > index {
+				(other.get() - 1).try_into().expect("Decremented index is still valid")
+			} else {
+				other
+			}
+		};
+		match &mut self.local_node {
+			LocalNode::Mixnode(local_index) =>
+				if *local_index == index {
+					self.local_node = LocalNode::NonMixnode(Vec::new());
+				} else {
+					*local_index = shift(*local_index);
+				},
+			LocalNode::NonMixnode(gateway_indices) => {
+				gateway_indices.retain(|gateway_index| *gateway_index != index);
+				for gateway_index in gateway_indices.iter_mut() {
+					*gateway_index = shift(*gateway_index);
+				}
+			},
+		}
+		self.fill_gateways(rng);
+
+		Ok(mixnode)
+	}
+
+	/// Returns `true` iff the local node is a mixnode.
 	pub fn is_mixnode(&self) -> bool {
 		matches!(self.local_node, LocalNode::Mixnode(_))
 	}
 
+	/// Returns the number of gateway mixnodes divided by the total number of mixnodes. Returns 0
+	/// if the local node is a mixnode (in which case it has no gateway mixnodes) or there are no
+	/// mixnodes.
+	pub fn gateway_to_mixnode_ratio(&self) -> f64 {
+		match &self.local_node {
+			LocalNode::NonMixnode(gateway_indices) if !self.mixnodes.is_empty() =>
+				(gateway_indices.len() as f64) / (self.mixnodes.len() as f64),
+			_ => 0.0,
+		}
+	}
+
+	/// Returns `true` iff the local node is not a mixnode and the mixnode with the given index is
+	/// one of its gateway mixnodes.
+	pub fn is_gateway(&self, index: MixnodeIndex) -> bool {
+		match &self.local_node {
+			LocalNode::Mixnode(_) => false,
+			LocalNode::NonMixnode(gateway_indices) => gateway_indices.contains(&index),
+		}
+	}
+
+	/// Check that `route`, the sequence of mixnodes a packet sent by the local node should visit,
+	/// is one the local node might generate itself. The route must:
+	///
+	/// - Be non-empty and contain no more than [`MAX_HOPS`] mixnodes.
+	/// - Only contain valid mixnode indices.
+	/// - Not visit any mixnode more than once, or visit the local node.
+	/// - Start at a gateway mixnode, if the local node is not a mixnode.
+	///
+	/// Note that gateway connectivity is not checked.
+	pub fn validate_route(&self, route: &[MixnodeIndex]) -> Result<(), TopologyErr> {
+		let Some(first_index) = route.first() else {
+			return Err(TopologyErr::InvalidRoute { reason: "Route is empty" })
+		};
+		if route.len() > MAX_HOPS {
+			return Err(TopologyErr::InvalidRoute { reason: "Route has too many hops" })
+		}
+		let mut used_indices = UsedIndices::new();
+		if let LocalNode::Mixnode(local_index) = self.local_node {
+			used_indices.insert(local_index);
+		}
+		for index in route {
+			if (index.get() as usize) >= self.mixnodes.len() {
+				return Err(TopologyErr::BadMixnodeIndex(*index))
+			}
+			if used_indices.contains(*index) {
+				return Err(TopologyErr::InvalidRoute {
+					reason: "Route visits a mixnode more than once, or visits the local node",
+				})
+			}
+			used_indices.insert(*index);
+		}
+		if matches!(self.local_node, LocalNode::NonMixnode(_)) && !self.is_gateway(*first_index) {
+			return Err(TopologyErr::InvalidRoute { reason: "First hop is not a gateway mixnode" })
+		}
+		Ok(())
+	}
+
+	/// Returns `true` if [`validate_route`](Self::validate_route) accepts `route`. In particular,
+	/// returns `false` if the route visits any mixnode more than once.
+	pub fn is_path_valid(&self, route: &[MixnodeIndex]) -> bool {
+		self.validate_route(route).is_ok()
+	}
+
+	/// Returns a measure of how evenly the local node's `num_hops`-hop routes are spread across
+	/// the mixnodes: the ratio of the minimum to the maximum number of routes through any single
+	/// mixnode. 1.0 means perfectly balanced; values approaching 0 mean some mixnodes see far more
+	/// of our traffic than others.
+	///
+	/// Routes are assumed to visit distinct mixnodes, as generated by this crate. If the
+	/// local node is a mixnode, all other mixnodes are interchangeable and the result is always
+	/// 1.0. Otherwise, the first hop is restricted to the gateway mixnodes, which therefore carry
+	/// more routes than the rest.
+	pub fn balance_factor(&self, num_hops: usize) -> f64 {
+		let LocalNode::NonMixnode(gateway_indices) = &self.local_node else { return 1.0 };
+		let num_gateways = gateway_indices.len();
+		if (num_gateways == 0) || (num_gateways == self.mixnodes.len()) {
+			// Either there are no routes at all, or all mixnodes are equivalent
+			return 1.0
+		}
+
+		// Both counts are divided through by the number of ways of filling the remaining
+		// num_hops - 2 hops, which is the same for gateway and non-gateway mixnodes. A gateway
+		// mixnode can be the first hop (with any other mixnodes following), or any later hop (with
+		// another gateway first). A non-gateway mixnode can only be a later hop.
+		let num_mixnodes = self.mixnodes.len() as f64;
+		let num_gateways = num_gateways as f64;
+		let num_later_hops = num_hops.saturating_sub(1) as f64;
+		let gateway_routes = (num_mixnodes - 1.0) + ((num_gateways - 1.0) * num_later_hops);
+		let non_gateway_routes = num_gateways * num_later_hops;
+		non_gateway_routes.min(gateway_routes) / non_gateway_routes.max(gateway_routes)
+	}
+
+	/// Returns the expected total forwarding delay for a packet traversing `num_hops` hops. The
+	/// packet is delayed at each hop except the last, with each delay drawn independently from an
+	/// exponential distribution with mean `mean_forwarding_delay`.
+	pub fn expected_path_latency(
+		&self,
+		num_hops: usize,
+		mean_forwarding_delay: Duration,
+	) -> Duration {
+		let num_delays = num_hops.saturating_sub(1);
+		mean_forwarding_delay * u32::try_from(num_delays).unwrap_or(u32::MAX)
+	}
+
+	/// Returns the variance, in seconds squared, of the total forwarding delay for a packet
+	/// traversing `num_hops` hops. See [`expected_path_latency`](Self::expected_path_latency).
+	/// The variance of an exponential distribution is the square of its mean.
+	pub fn expected_path_latency_variance(
+		&self,
+		num_hops: usize,
+		mean_forwarding_delay: Duration,
+	) -> f64 {
+		let num_delays = num_hops.saturating_sub(1);
+		(num_delays as f64) * mean_forwarding_delay.as_secs_f64().powi(2)
+	}
+
+	/// Returns the expected proportion of authored packets which are cover packets, as opposed to
+	/// real (request or reply) packets. `authored_queue_fill` is the proportion of time the
+	/// authored packet queue is non-empty; the current queue fill ratio is a reasonable estimate
+	/// of this. `loop_cover_proportion` should match [`Config::loop_cover_proportion`].
+	///
+	/// Each authored packet is a loop cover packet with probability `loop_cover_proportion`;
+	/// otherwise a real packet is sent if one is queued. Note that the mean authored packet
+	/// period does not affect the proportion, only the absolute rates.
+	///
+	/// [`Config::loop_cover_proportion`]: super::Config::loop_cover_proportion
+	pub fn expected_cover_to_real_ratio(
+		&self,
+		authored_queue_fill: f64,
+		loop_cover_proportion: f64,
+	) -> f64 {
+		let drop_proportion = 1.0 - loop_cover_proportion.clamp(0.0, 1.0);
+		1.0 - (drop_proportion * authored_queue_fill.clamp(0.0, 1.0))
+	}
+
+	/// Returns the average number of mixnodes that could be used as intermediate hops between a
+	/// source and destination mixnode, across all source-destination pairs the local node might
+	/// use, divided by the total number of mixnodes. Higher is better; a score close to 1 means
+	/// almost every mixnode can be used to carry any fragment of a message.
+	///
+	/// Intermediate hops are chosen uniformly from all mixnodes other than the source, the
+	/// destination, and the local node, so every pair has the same number of candidates. Returns
+	/// 0 if there are no mixnodes.
+	pub fn route_diversity_score(&self) -> f64 {
+		let num_mixnodes = self.mixnodes.len();
+		if num_mixnodes == 0 {
+			return 0.0
+		}
+		// Routes never visit the local node
+		let num_usable = num_mixnodes - (self.is_mixnode() as usize);
+		(num_usable.saturating_sub(2) as f64) / (num_mixnodes as f64)
+	}
+
+	/// Returns an iterator over the degrees of the mixnodes, in index order. The degree of a
+	/// mixnode is the number of mixnodes that could follow it in a route generated by the local
+	/// node: every mixnode except itself and the local node.
+	fn degrees(&self) -> impl Iterator<Item = usize> + '_ {
+		let local_index = match &self.local_node {
+			LocalNode::Mixnode(index) => Some(index.get() as usize),
+			LocalNode::NonMixnode(_) => None,
+		};
+		let num_others = self.mixnodes.len().saturating_sub(1);
+		(0..self.mixnodes.len()).map(move |index| match local_index {
+			Some(local_index) if local_index != index => num_others - 1,
+			_ => num_others,
+		})
+	}
+
+	/// Returns the median mixnode degree; see [`degree_distribution`](Self::degree_distribution).
+	/// Returns 0 if there are no mixnodes.
+	pub fn median_degree(&self) -> f64 {
+		let mut degrees: Vec<_> = self.degrees().collect();
+		if degrees.is_empty() {
+			return 0.0
+		}
+		degrees.sort_unstable();
+		let mid = degrees.len() / 2;
+		if degrees.len() % 2 == 0 {
+			((degrees[mid - 1] + degrees[mid]) as f64) / 2.0
+		} else {
+			degrees[mid] as f64
+		}
+	}
+
+	/// Returns `(degree, count)` pairs, in increasing order of degree, giving the number of
+	/// mixnodes with each degree. The degree of a mixnode is the number of mixnodes that could
+	/// follow it in a route generated by the local node. Any mixnode may follow any other, except
+	/// that routes never visit the local node, so the distribution is very simple; this is
+	/// intended for automated topology quality checks.
+	pub fn degree_distribution(&self) -> Vec<(usize, usize)> {
+		let mut distribution: Vec<(usize, usize)> = Vec::new();
+		let mut degrees: Vec<_> = self.degrees().collect();
+		degrees.sort_unstable();
+		for degree in degrees {
+			match distribution.last_mut() {
+				Some((last_degree, count)) if *last_degree == degree => *count += 1,
+				_ => distribution.push((degree, 1)),
+			}
+		}
+		distribution
+	}
+
+	/// Returns the average local clustering coefficient across all mixnodes, using the same
+	/// graph as [`degree_distribution`](Self::degree_distribution). The local clustering
+	/// coefficient of a mixnode is the proportion of pairs of its neighbours that are themselves
+	/// connected, or 0 if it has fewer than two neighbours. High clustering could create traffic
+	/// analysis opportunities. Returns 0 if there are no mixnodes.
+	///
+	/// As any mixnode may follow any other (excluding the local node), all pairs of neighbours are
+	/// connected, and so this is just the proportion of mixnodes with at least two neighbours.
+	/// This is O(n) in the number of mixnodes, rather than the O(n²) (or worse) required for an
+	/// arbitrary graph.
+	#[cfg(feature = "research")]
+	pub fn cluster_coefficient(&self) -> f64 {
+		if self.mixnodes.is_empty() {
+			return 0.0
+		}
+		let num_clustered = self.degrees().filter(|degree| *degree >= 2).count();
+		(num_clustered as f64) / (self.mixnodes.len() as f64)
+	}
+
+	/// Returns a new topology containing only the mixnodes at `mixnode_indices`, in the given
+	/// order, re-indexed from 0. Out-of-range and repeated indices are ignored. The local node
+	/// remains a mixnode if it is included. Otherwise, its gateway mixnodes are those of the
+	/// current gateway mixnodes that are included; new gateways are not chosen.
+	///
+	/// This is intended for research, eg studying the effect of a subset of mixnodes colluding to
+	/// drop packets. It should not be used to build topologies for real sessions, as all nodes
+	/// must agree on the mixnode indices.
+	#[cfg(feature = "research")]
+	pub fn subgraph(&self, mixnode_indices: &[MixnodeIndex]) -> Self
+	where
+		X: Clone,
+	{
+		let mut old_to_new = std::collections::BTreeMap::new();
+		let mut mixnodes = Vec::with_capacity(mixnode_indices.len());
+		for &old_index in mixnode_indices {
+			let Some(mixnode) = self.mixnodes.get(old_index.get() as usize) else { continue };
+			let new_index: MixnodeIndex = mixnodes
+				.len()
+				.try_into()
+				.expect("Subset of a valid topology, so no more than MAX_MIXNODE_INDEX + 1");
+			if old_to_new.insert(old_index, new_index).is_none() {
+				mixnodes.push(mixnode.clone());
+			}
+		}
+
+		let local_node = match &self.local_node {
+			LocalNode::Mixnode(local_index) => match old_to_new.get(local_index) {
+				Some(new_index) => LocalNode::Mixnode(*new_index),
+				None => LocalNode::NonMixnode(Vec::new()),
+			},
+			LocalNode::NonMixnode(gateway_indices) => LocalNode::NonMixnode(
+				gateway_indices.iter().filter_map(|index| old_to_new.get(index).copied()).collect(),
+			),
+		};
+
+		Self {
+			mixnodes,
+			local_kx_public: self.local_kx_public,
+			local_node,
+			num_gateway_mixnodes: self.num_gateway_mixnodes,
+		}
+	}
+
+	/// Returns the base-2 logarithm of the number of distinct `num_hops`-hop routes the local node
+	/// might generate (see [`validate_route`](Self::validate_route)). This is an upper bound on
+	/// the anonymity, in bits, provided by route selection, and is intended for comparing
+	/// topology configurations. Gateway connectivity is ignored. Returns 0 if there are no such
+	/// routes.
+	pub fn expected_anonymity_bits(&self, num_hops: usize) -> f64 {
+		let Some(num_other_hops) = num_hops.checked_sub(1) else { return 0.0 };
+		// Routes never visit the local node
+		let num_usable = self.mixnodes.len() - (self.is_mixnode() as usize);
+		let num_first = match &self.local_node {
+			LocalNode::Mixnode(_) => num_usable,
+			LocalNode::NonMixnode(gateway_indices) => gateway_indices.len(),
+		};
+		// The remaining hops are distinct mixnodes chosen from the num_usable - 1 others
+		if (num_first == 0) || (num_usable <= num_other_hops) {
+			return 0.0
+		}
+		(1..=num_other_hops)
+			.map(|i| ((num_usable - i) as f64).log2())
+			.sum::<f64>() +
+			(num_first as f64).log2()
+	}
+
+	/// Returns the probability that two random `num_hops`-hop routes, one starting at mixnode `a`
+	/// and the other at mixnode `b`, have at least one mixnode in common. Each route is assumed
+	/// to visit distinct mixnodes, chosen uniformly from all mixnodes; the local node and gateway
+	/// restrictions are ignored. This is purely informational.
+	pub fn path_intersection_probability(
+		&self,
+		a: MixnodeIndex,
+		b: MixnodeIndex,
+		num_hops: usize,
+	) -> f64 {
+		let num_mixnodes = self.mixnodes.len();
+		debug_assert!((a.get() as usize) < num_mixnodes);
+		debug_assert!((b.get() as usize) < num_mixnodes);
+		if a == b {
+			return 1.0
+		}
+		if num_hops > num_mixnodes {
+			// Can't have routes with this many distinct mixnodes
+			return 1.0
+		}
+		let Some(num_other_hops) = num_hops.checked_sub(1) else { return 0.0 };
+
+		// Route A is a plus num_other_hops mixnodes chosen from the num_mixnodes - 1 others. It
+		// avoids b with probability C(n - 2, h - 1) / C(n - 1, h - 1) = (n - h) / (n - 1).
+		let n = num_mixnodes as f64;
+		let h = num_hops as f64;
+		let mut disjoint = (n - h) / (n - 1.0);
+		// Given that, route B avoids route A if its other num_other_hops mixnodes are chosen
+		// from the num_mixnodes - 1 - num_hops mixnodes not in route A (or b). This happens with
+		// probability C(n - 1 - h, h - 1) / C(n - 1, h - 1).
+		for i in 0..num_other_hops {
+			let i = i as f64;
+			disjoint *= ((n - 1.0 - h - i) / (n - 1.0 - i)).max(0.0);
+		}
+		1.0 - disjoint
+	}
+
+	/// Choose `n` distinct gateway mixnodes at random, for sending the same message via multiple
+	/// paths. If the local node is a mixnode, every other mixnode is considered a gateway. Fails
+	/// with [`TopologyErr::InsufficientGateways`] if there are fewer than `n` gateways.
+	pub fn choose_n_distinct_gateways(
+		&self,
+		rng: &mut impl Rng,
+		n: usize,
+	) -> Result<Vec<MixnodeIndex>, TopologyErr> {
+		let gateway_indices: Vec<MixnodeIndex> = match &self.local_node {
+			LocalNode::Mixnode(local_index) => (0..self.mixnodes.len())
+				.map(|index| {
+					index.try_into().expect("Topology::new() contract limits size of mixnode set")
+				})
+				.filter(|index| index != local_index)
+				.collect(),
+			LocalNode::NonMixnode(gateway_indices) => gateway_indices.clone(),
+		};
+		if gateway_indices.len() < n {
+			return Err(TopologyErr::InsufficientGateways { need: n, have: gateway_indices.len() })
+		}
+		Ok(gateway_indices.choose_multiple(rng, n).copied().collect())
+	}
+
+	/// Choose `n` distinct mixnodes at random to be the first hops of packets sent by the local
+	/// node, so that the fragments of a message can be sent via different paths. If the local
+	/// node is a mixnode, any other mixnode may be a first hop; otherwise only gateway mixnodes
+	/// may be. Fails with [`TopologyErr::InsufficientMixnodes`] if there are fewer than `n`
+	/// candidates.
+	pub fn first_hop_candidates(
+		&self,
+		rng: &mut impl Rng,
+		n: usize,
+	) -> Result<Vec<MixnodeIndex>, TopologyErr> {
+		let candidates = self.first_hop_candidates_excluding(rng, n, None);
+		if candidates.len() < n {
+			return Err(TopologyErr::InsufficientMixnodes { need: n, have: candidates.len() })
+		}
+		Ok(candidates)
+	}
+
+	/// Like [`first_hop_candidates`](Self::first_hop_candidates), but never chooses
+	/// `exclude_index`, and returns fewer than `max` mixnodes instead of failing if there are not
+	/// enough candidates. `exclude_index` is typically the destination of the packets, which
+	/// cannot also be their first hop.
+	pub fn first_hop_candidates_excluding(
+		&self,
+		rng: &mut impl Rng,
+		max: usize,
+		exclude_index: Option<MixnodeIndex>,
+	) -> Vec<MixnodeIndex> {
+		let candidates: Vec<MixnodeIndex> = match &self.local_node {
+			LocalNode::Mixnode(local_index) => (0..self.mixnodes.len())
+				.map(|index| {
+					index.try_into().expect("Topology::new() contract limits size of mixnode set")
+				})
+				.filter(|index| (index != local_index) && (Some(*index) != exclude_index))
+				.collect(),
+			LocalNode::NonMixnode(gateway_indices) => gateway_indices
+				.iter()
+				.copied()
+				.filter(|index| Some(*index) != exclude_index)
+				.collect(),
+		};
+		candidates.choose_multiple(rng, max).copied().collect()
+	}
+
+	/// Returns the mixnodes the local node should try to maintain connections to.
 	pub fn reserved_peers(&self) -> impl Iterator<Item = &Mixnode<X>> {
 		let indices = match &self.local_node {
 			LocalNode::Mixnode(local_index) => Either::Left({
@@ -141,6 +835,28 @@ impl<X> Topology<X> {
 		indices.map(|index| &self.mixnodes[index as usize])
 	}
 
+	/// Returns the mixnode with the given index, or [`None`] if the index is out of range. Crate
+	/// users typically keep the addresses of a mixnode in [`Mixnode::extra`], so this can be used
+	/// to look up the addresses of a mixnode.
+	pub fn mixnode(&self, index: MixnodeIndex) -> Option<&Mixnode<X>> {
+		self.mixnodes.get(index.get() as usize)
+	}
+
+	/// Returns the index of the mixnode with the given peer ID, along with the mixnode itself
+	/// (including [`Mixnode::extra`]), or [`None`] if there is no such mixnode. If multiple
+	/// mixnodes have the same peer ID, the one with the lowest index is returned.
+	pub fn mixnode_for_peer_id(&self, peer_id: &PeerId) -> Option<(MixnodeIndex, &Mixnode<X>)> {
+		let index = self.mixnode_index_from_peer_id(peer_id)?;
+		Some((index, &self.mixnodes[index.get() as usize]))
+	}
+
+	/// Returns the key-exchange public key of the mixnode with the given index, or [`None`] if the
+	/// index is out of range.
+	pub fn kx_public_for_mixnode(&self, index: MixnodeIndex) -> Option<&KxPublic> {
+		self.mixnodes.get(index.get() as usize).map(|mixnode| &mixnode.kx_public)
+	}
+
+	/// Returns the peer ID of the mixnode with the given index.
 	pub fn mixnode_index_to_peer_id(&self, index: MixnodeIndex) -> Result<PeerId, TopologyErr> {
 		self.mixnodes
 			.get(index.get() as usize)
@@ -148,6 +864,15 @@ impl<X> Topology<X> {
 			.ok_or(TopologyErr::BadMixnodeIndex(index))
 	}
 
+	/// Returns the index of the mixnode with the given peer ID, or [`None`] if there is no such
+	/// mixnode. If multiple mixnodes have the same peer ID, the lowest index is returned.
+	pub fn mixnode_index_from_peer_id(&self, peer_id: &PeerId) -> Option<MixnodeIndex> {
+		self.mixnodes.iter().position(|mixnode| &mixnode.peer_id == peer_id).map(|index| {
+			index.try_into().expect("Topology::new() contract limits size of mixnode set")
+		})
+	}
+
+	/// Returns the peer ID corresponding to a packet target.
 	pub fn target_to_peer_id(&self, target: &Target) -> Result<PeerId, TopologyErr> {
 		match target {
 			Target::MixnodeIndex(index) => self.mixnode_index_to_peer_id(*index),
@@ -156,6 +881,71 @@ impl<X> Topology<X> {
 	}
 }
 
+#[cfg(feature = "json")]
+impl<X: Default> Topology<X> {
+	/// Build a new topology from a JSON array of mixnodes. Each mixnode should be an object with
+	/// hex-encoded `kx_public` and `peer_id` fields. The extra data for each mixnode is set to
+	/// `X::default()`. See [`new`](Self::new) for a description of the other parameters.
+	pub fn new_from_json(
+		rng: &mut impl Rng,
+		json: &str,
+		local_kx_public: &KxPublic,
+		num_gateway_mixnodes: u32,
+	) -> Result<Self, JsonErr> {
+		let mixnodes: Vec<JsonMixnode> = serde_json::from_str(json)?;
+		if mixnodes.len() > (MAX_MIXNODE_INDEX + 1) as usize {
+			return Err(JsonErr::TooManyMixnodes(mixnodes.len()))
+		}
+		let mixnodes = mixnodes
+			.into_iter()
+			.map(|mixnode| Mixnode {
+				kx_public: mixnode.kx_public,
+				peer_id: mixnode.peer_id,
+				extra: X::default(),
+			})
+			.collect();
+		Ok(Self::new(rng, mixnodes, local_kx_public, num_gateway_mixnodes))
+	}
+}
+
+#[cfg(feature = "json")]
+impl<X> Topology<X> {
+	/// Returns the mixnodes as a JSON array, in the format accepted by
+	/// [`new_from_json`](Self::new_from_json). Extra data is not included.
+	pub fn to_json(&self) -> String {
+		let mixnodes: Vec<_> = self
+			.mixnodes
+			.iter()
+			.map(|mixnode| JsonMixnode { kx_public: mixnode.kx_public, peer_id: mixnode.peer_id })
+			.collect();
+		serde_json::to_string(&mixnodes).expect("Serialization of mixnodes cannot fail")
+	}
+}
+
+#[cfg(feature = "scale")]
+impl<X: Default> Topology<X> {
+	/// Build a new topology from a SCALE-encoded list of mixnodes, as might be stored on chain.
+	/// Each mixnode should be encoded as a `(KxPublic, PeerId)` tuple. The extra data for each
+	/// mixnode is set to the default. See [`new`](Self::new) for a description of the other
+	/// parameters.
+	pub fn new_from_scale_encoded(
+		rng: &mut impl Rng,
+		mut bytes: &[u8],
+		local_kx_public: &KxPublic,
+		num_gateway_mixnodes: u32,
+	) -> Result<Self, ScaleErr> {
+		let mixnodes: Vec<(KxPublic, PeerId)> = codec::DecodeAll::decode_all(&mut bytes)?;
+		if mixnodes.len() > (MAX_MIXNODE_INDEX + 1) as usize {
+			return Err(ScaleErr::TooManyMixnodes(mixnodes.len()))
+		}
+		let mixnodes = mixnodes
+			.into_iter()
+			.map(|(kx_public, peer_id)| Mixnode { kx_public, peer_id, extra: X::default() })
+			.collect();
+		Ok(Self::new(rng, mixnodes, local_kx_public, num_gateway_mixnodes))
+	}
+}
+
 impl<X> fmt::Display for Topology<X> {
 	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
 		match &self.local_node {
@@ -211,6 +1001,10 @@ impl UsedIndices {
 		}
 	}
 
+	fn contains(&self, index: MixnodeIndex) -> bool {
+		self.0.contains(&index)
+	}
+
 	fn iter(&self) -> impl ExactSizeIterator<Item = MixnodeIndex> + '_ {
 		self.0.iter().copied()
 	}
@@ -227,6 +1021,8 @@ pub struct RouteGenerator<'topology, X> {
 	/// Always empty if the local node is a mixnode. Otherwise, the subset of the gateway mixnodes
 	/// from the topology that are currently connected.
 	connected_gateway_indices: ArrayVec<MixnodeIndex, MAX_CONNECTED_GATEWAY_INDICES>,
+	/// If not [`None`], routes from the local node must start at this mixnode.
+	forced_first_index: Option<MixnodeIndex>,
 }
 
 impl<'topology, X> RouteGenerator<'topology, X> {
@@ -247,7 +1043,20 @@ impl<'topology, X> RouteGenerator<'topology, X> {
 				.collect(),
 		};
 
-		Self { topology, local_peer_id: ns.local_peer_id(), connected_gateway_indices }
+		Self {
+			topology,
+			local_peer_id: ns.local_peer_id(),
+			connected_gateway_indices,
+			forced_first_index: None,
+		}
+	}
+
+	/// Force routes from the local node to start at the mixnode with the given index. The index
+	/// must be valid and must not be the local node's index. If the local node is not a mixnode,
+	/// the mixnode should be a connected gateway mixnode.
+	pub fn force_first_index(&mut self, index: MixnodeIndex) {
+		debug_assert!((index.get() as usize) < self.topology.mixnodes.len());
+		self.forced_first_index = Some(index);
 	}
 
 	pub fn topology(&self) -> &'topology Topology<X> {
@@ -290,17 +1099,27 @@ impl<'topology, X> RouteGenerator<'topology, X> {
 		&self,
 		rng: &mut (impl Rng + CryptoRng),
 	) -> Result<MixnodeIndex, TopologyErr> {
-		let exclude_index = match self.topology.local_node {
-			// If we're a mixnode, don't send to ourselves
-			LocalNode::Mixnode(local_index) => Some(local_index),
-			// If we're not a mixnode, and we are only connected to one gateway mixnode, don't send
-			// to it; it must be the first hop, and we don't want to visit any node more than once
-			LocalNode::NonMixnode(_) => match self.connected_gateway_indices.as_slice() {
+		let mut exclude_indices = UsedIndices::new();
+
+		// If we're a mixnode, don't send to ourselves
+		if let LocalNode::Mixnode(local_index) = self.topology.local_node {
+			exclude_indices.insert(local_index);
+		}
+
+		// If we know what the first hop will be, don't send to it; we don't want to visit any node
+		// more than once. We know the first hop if it has been forced, or if we're not a mixnode
+		// and we are only connected to one gateway mixnode (connected_gateway_indices is always
+		// empty if we're a mixnode).
+		let first_index =
+			self.forced_first_index.or(match self.connected_gateway_indices.as_slice() {
 				[gateway_index] => Some(*gateway_index),
 				_ => None,
-			},
-		};
-		self.choose_mixnode_index(rng, exclude_index.iter().copied())
+			});
+		if let Some(first_index) = first_index {
+			exclude_indices.insert(first_index);
+		}
+
+		self.choose_mixnode_index(rng, exclude_indices.iter())
 	}
 
 	fn choose_connected_gateway_index(
@@ -310,7 +1129,7 @@ impl<'topology, X> RouteGenerator<'topology, X> {
 	) -> Result<MixnodeIndex, TopologyErr> {
 		try_exclude_index
 			.and_then(|try_exclude_index| {
-				if !self.connected_gateway_indices.iter().any(|index| *index == try_exclude_index) {
+				if !self.connected_gateway_indices.contains(&try_exclude_index) {
 					// Mixnode to exclude is not a connected gateway
 					return None
 				}
@@ -336,6 +1155,22 @@ impl<'topology, X> RouteGenerator<'topology, X> {
 		rng: &mut (impl Rng + CryptoRng),
 		kind: RouteKind,
 		num_hops: usize,
+	) -> Result<MixnodeIndex, TopologyErr> {
+		let forced_first_index = self.forced_first_index;
+		self.gen_route_via(targets, their_kx_publics, rng, kind, num_hops, forced_first_index)
+	}
+
+	/// Like [`gen_route`](Self::gen_route), but if `forced_first_index` is not [`None`], routes
+	/// from the local node start at this mixnode, instead of the one passed to
+	/// [`force_first_index`](Self::force_first_index). The same restrictions apply.
+	pub fn gen_route_via(
+		&self,
+		targets: &mut ArrayVec<Target, { MAX_HOPS - 1 }>,
+		their_kx_publics: &mut ArrayVec<KxPublic, MAX_HOPS>,
+		rng: &mut (impl Rng + CryptoRng),
+		kind: RouteKind,
+		num_hops: usize,
+		forced_first_index: Option<MixnodeIndex>,
 	) -> Result<MixnodeIndex, TopologyErr> {
 		// Mixnode indices we've used already. We avoid using any mixnode more than once.
 		let mut used_indices = UsedIndices::new();
@@ -358,10 +1193,16 @@ impl<'topology, X> RouteGenerator<'topology, X> {
 			used_indices.insert(index);
 		}
 
-		// If we're not a mixnode, and the packet is to be sent by us, the first hop needs to be to
-		// a connected gateway mixnode
-		let special_first_index = match self.topology.local_node {
-			LocalNode::NonMixnode(_) if from_local => {
+		// If the packet is to be sent by us, the first hop may have been forced. Otherwise, if
+		// we're not a mixnode, and the packet is to be sent by us, the first hop needs to be to a
+		// connected gateway mixnode.
+		let special_first_index = match (forced_first_index, &self.topology.local_node) {
+			(Some(index), _) if from_local => {
+				debug_assert!(!used_indices.contains(index));
+				used_indices.insert(index);
+				Some(index)
+			},
+			(_, LocalNode::NonMixnode(_)) if from_local => {
 				let index = self.choose_connected_gateway_index(rng, used_indices.as_option())?;
 				used_indices.insert(index);
 				Some(index)
@@ -448,3 +1289,402 @@ impl<'topology, X> RouteGenerator<'topology, X> {
 		Ok(first_index.expect("At least one hop"))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn mixnode(kx_public: KxPublic) -> Mixnode<()> {
+		Mixnode { kx_public, peer_id: [0; 32], extra: () }
+	}
+
+	fn index(index: usize) -> MixnodeIndex {
+		index.try_into().unwrap()
+	}
+
+	#[test]
+	fn add_and_remove_mixnodes() {
+		let mut rng = rand::thread_rng();
+		let local_kx_public = [2; 32];
+		let mixnodes = (0..5).map(|i| mixnode([i; 32])).collect();
+		let mut topology = Topology::new(&mut rng, mixnodes, &local_kx_public, 3);
+		assert!(matches!(topology.local_node, LocalNode::Mixnode(i) if i == index(2)));
+
+		// Removing an earlier mixnode shifts the local index down
+		assert_eq!(topology.remove_mixnode(&mut rng, index(0)).unwrap().kx_public, [0; 32]);
+		assert!(matches!(topology.local_node, LocalNode::Mixnode(i) if i == index(1)));
+
+		// Removing the local node turns it into a non-mixnode with gateways
+		topology.remove_mixnode(&mut rng, index(1)).unwrap();
+		assert!(!topology.is_mixnode());
+		assert_eq!(topology.reserved_peers().count(), 3);
+		assert!(matches!(
+			topology.remove_mixnode(&mut rng, index(3)),
+			Err(TopologyErr::BadMixnodeIndex(_))
+		));
+
+		// Removing a gateway leaves too few mixnodes to replace it
+		topology.remove_mixnode(&mut rng, index(0)).unwrap();
+		assert_eq!(topology.reserved_peers().count(), 2);
+		assert!(topology.is_gateway(index(0)) && topology.is_gateway(index(1)));
+
+		// Adding a mixnode tops up the gateways
+		assert_eq!(topology.add_mixnode(&mut rng, mixnode([5; 32])).unwrap(), index(2));
+		assert!(topology.is_gateway(index(2)));
+
+		// Adding the local node makes it a mixnode again
+		assert_eq!(topology.add_mixnode(&mut rng, mixnode(local_kx_public)).unwrap(), index(3));
+		assert!(matches!(topology.local_node, LocalNode::Mixnode(i) if i == index(3)));
+	}
+
+	#[cfg(feature = "json")]
+	#[test]
+	fn mixnode_serde() {
+		let mixnode = Mixnode { kx_public: [0xab; 32], peer_id: [0x01; 32], extra: 42u32 };
+		let json = serde_json::to_string(&mixnode).unwrap();
+		assert_eq!(
+			json,
+			format!(
+				r#"{{"kx_public":"{}","peer_id":"{}","extra":42}}"#,
+				"ab".repeat(32),
+				"01".repeat(32)
+			)
+		);
+		let decoded: Mixnode<u32> = serde_json::from_str(&json).unwrap();
+		assert_eq!(decoded.kx_public, mixnode.kx_public);
+		assert_eq!(decoded.peer_id, mixnode.peer_id);
+		assert_eq!(decoded.extra, 42);
+	}
+
+	#[cfg(feature = "scale")]
+	#[test]
+	fn new_from_scale_encoded() {
+		use codec::Encode;
+
+		let mut rng = rand::thread_rng();
+		let mixnodes: Vec<(KxPublic, PeerId)> = (0..5).map(|i| ([i; 32], [i + 1; 32])).collect();
+		let encoded = mixnodes.encode();
+
+		let topology =
+			Topology::<()>::new_from_scale_encoded(&mut rng, &encoded, &[2; 32], 3).unwrap();
+		assert!(matches!(topology.local_node, LocalNode::Mixnode(i) if i == index(2)));
+		assert_eq!(topology.mixnode_index_to_peer_id(index(4)).unwrap(), [5; 32]);
+
+		assert!(matches!(
+			Topology::<()>::new_from_scale_encoded(&mut rng, &encoded[1..], &[2; 32], 3),
+			Err(ScaleErr::Decode(_))
+		));
+	}
+
+	#[test]
+	fn validate_route() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = || (0..10).map(|i| mixnode([i; 32])).collect();
+
+		let topology = Topology::new(&mut rng, mixnodes(), &[2; 32], 3);
+		assert!(topology.validate_route(&[index(0), index(5), index(9)]).is_ok());
+		assert!(matches!(topology.validate_route(&[]), Err(TopologyErr::InvalidRoute { .. })));
+		assert!(matches!(
+			topology.validate_route(&[index(0), index(2)]),
+			Err(TopologyErr::InvalidRoute { .. })
+		));
+		assert!(matches!(
+			topology.validate_route(&[index(0), index(1), index(0)]),
+			Err(TopologyErr::InvalidRoute { .. })
+		));
+		assert!(matches!(
+			topology.validate_route(&[index(0), index(10)]),
+			Err(TopologyErr::BadMixnodeIndex(_))
+		));
+
+		let topology = Topology::new(&mut rng, mixnodes(), &[10; 32], 3);
+		let gateway_index = topology.reserved_peers().next().unwrap().kx_public[0] as usize;
+		let non_gateway_index = (0..10).find(|i| !topology.is_gateway(index(*i))).unwrap();
+		assert!(topology.validate_route(&[index(gateway_index), index(non_gateway_index)]).is_ok());
+		assert!(matches!(
+			topology.validate_route(&[index(non_gateway_index), index(gateway_index)]),
+			Err(TopologyErr::InvalidRoute { .. })
+		));
+		assert!(topology.is_path_valid(&[index(gateway_index), index(non_gateway_index)]));
+		assert!(!topology.is_path_valid(&[index(gateway_index), index(gateway_index)]));
+	}
+
+	#[test]
+	fn path_intersection_probability() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = |n| (0..n).map(|i| mixnode([i; 32])).collect();
+
+		// Routes {0, x} and {1, y}, with x != 0 and y != 1, are disjoint for 2 of the 9 choices
+		// of x and y
+		let topology = Topology::new(&mut rng, mixnodes(4), &[4; 32], 3);
+		let probability = topology.path_intersection_probability(index(0), index(1), 2);
+		assert!((probability - (7.0 / 9.0)).abs() < 1e-12);
+		assert_eq!(topology.path_intersection_probability(index(0), index(0), 2), 1.0);
+		assert_eq!(topology.path_intersection_probability(index(0), index(1), 1), 0.0);
+		assert_eq!(topology.path_intersection_probability(index(0), index(1), 3), 1.0);
+	}
+
+	#[test]
+	fn choose_n_distinct_gateways() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = || (0..10).map(|i| mixnode([i; 32])).collect();
+
+		let topology = Topology::new(&mut rng, mixnodes(), &[10; 32], 3);
+		let mut gateways = topology.choose_n_distinct_gateways(&mut rng, 3).unwrap();
+		assert!(gateways.iter().all(|index| topology.is_gateway(*index)));
+		gateways.sort_by_key(|index| index.get());
+		gateways.dedup();
+		assert_eq!(gateways.len(), 3);
+		assert!(matches!(
+			topology.choose_n_distinct_gateways(&mut rng, 4),
+			Err(TopologyErr::InsufficientGateways { need: 4, have: 3 })
+		));
+
+		let topology = Topology::new(&mut rng, mixnodes(), &[2; 32], 3);
+		let gateways = topology.choose_n_distinct_gateways(&mut rng, 9).unwrap();
+		assert!(!gateways.contains(&index(2)));
+	}
+
+	#[test]
+	fn first_hop_candidates() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = || (0..10).map(|i| mixnode([i; 32])).collect();
+
+		let topology = Topology::new(&mut rng, mixnodes(), &[2; 32], 3);
+		let mut candidates = topology.first_hop_candidates_excluding(&mut rng, 9, Some(index(5)));
+		candidates.sort_by_key(|index| index.get());
+		assert_eq!(candidates, [0, 1, 3, 4, 6, 7, 8, 9].map(index));
+		assert!(matches!(
+			topology.first_hop_candidates(&mut rng, 10),
+			Err(TopologyErr::InsufficientMixnodes { need: 10, have: 9 })
+		));
+
+		let topology = Topology::new(&mut rng, mixnodes(), &[10; 32], 3);
+		let candidates = topology.first_hop_candidates(&mut rng, 3).unwrap();
+		assert!(candidates.iter().all(|index| topology.is_gateway(*index)));
+		assert!(matches!(
+			topology.first_hop_candidates(&mut rng, 4),
+			Err(TopologyErr::InsufficientMixnodes { need: 4, have: 3 })
+		));
+	}
+
+	#[test]
+	fn mixnode_lookup() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = (0..5)
+			.map(|i| Mixnode { kx_public: [i; 32], peer_id: [i; 32], extra: format!("addr{i}") })
+			.collect();
+		let topology = Topology::new(&mut rng, mixnodes, &[2; 32], 3);
+
+		assert_eq!(topology.mixnode(index(3)).unwrap().extra, "addr3");
+		assert!(topology.mixnode(index(5)).is_none());
+
+		let (found_index, mixnode) = topology.mixnode_for_peer_id(&[4; 32]).unwrap();
+		assert_eq!(found_index, index(4));
+		assert_eq!(mixnode.extra, "addr4");
+		assert!(topology.mixnode_for_peer_id(&[5; 32]).is_none());
+	}
+
+	#[test]
+	fn resize_gateway_set() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = (0..10).map(|i| mixnode([i; 32])).collect();
+		let mut topology = Topology::new(&mut rng, mixnodes, &[10; 32], 3);
+		let gateways: Vec<_> = (0..10).filter(|i| topology.is_gateway(index(*i))).collect();
+
+		topology.resize_gateway_set(&mut rng, 5);
+		assert_eq!(topology.reserved_peers().count(), 5);
+		// Existing gateways are kept
+		assert!(gateways.iter().all(|i| topology.is_gateway(index(*i))));
+
+		topology.resize_gateway_set(&mut rng, 2);
+		assert_eq!(topology.reserved_peers().count(), 2);
+
+		topology.resize_gateway_set(&mut rng, 20);
+		assert_eq!(topology.reserved_peers().count(), 10);
+	}
+
+	#[test]
+	fn new_ring() {
+		let mixnodes: Vec<_> = (0..5).map(|i| mixnode([i; 32])).collect();
+		let topology = Topology::new_ring(mixnodes.clone(), &[5; 32]);
+		assert!(!topology.is_mixnode());
+		assert!((0..5).all(|i| topology.is_gateway(index(i))));
+		assert_eq!(topology.kx_public_for_mixnode(index(3)), Some(&[3; 32]));
+
+		let topology = Topology::new_ring(mixnodes, &[2; 32]);
+		assert!(topology.is_mixnode());
+		assert_eq!(topology.reserved_peers().count(), 4);
+	}
+
+	#[test]
+	fn gateway_to_mixnode_ratio() {
+		let mut rng = rand::thread_rng();
+		let mixnodes: Vec<_> = (0..10).map(|i| mixnode([i; 32])).collect();
+		let topology = Topology::new(&mut rng, mixnodes.clone(), &[10; 32], 3);
+		assert_eq!(topology.gateway_to_mixnode_ratio(), 0.3);
+		let topology = Topology::new(&mut rng, mixnodes, &[2; 32], 3);
+		assert_eq!(topology.gateway_to_mixnode_ratio(), 0.0);
+		let topology = Topology::<()>::new(&mut rng, Vec::new(), &[10; 32], 3);
+		assert_eq!(topology.gateway_to_mixnode_ratio(), 0.0);
+	}
+
+	#[test]
+	fn new_from_mixnode_iter() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = (0..5).map(|i| mixnode([i; 32]));
+		let topology = Topology::new_from_mixnode_iter(&mut rng, mixnodes, &[2; 32], 3).unwrap();
+		assert!(topology.is_mixnode());
+		assert_eq!(topology.reserved_peers().count(), 4);
+
+		let max_mixnodes = (MAX_MIXNODE_INDEX + 1) as usize;
+		let mixnodes = std::iter::repeat_with(|| mixnode([0; 32])).take(max_mixnodes + 1);
+		assert!(matches!(
+			Topology::new_from_mixnode_iter(&mut rng, mixnodes, &[1; 32], 3),
+			Err(TopologyErr::TooManyMixnodes)
+		));
+	}
+
+	#[test]
+	fn new_validate_unique_peer_ids() {
+		let mut rng = rand::thread_rng();
+		let mut mixnodes: Vec<_> =
+			(0..5).map(|i| Mixnode { kx_public: [i; 32], peer_id: [i; 32], extra: () }).collect();
+		assert!(Topology::new_validate_unique_peer_ids(
+			&mut rng,
+			mixnodes.clone(),
+			&[2; 32],
+			3,
+			"mixnet"
+		)
+		.is_ok());
+		mixnodes[4].peer_id = [1; 32];
+		assert!(matches!(
+			Topology::new_validate_unique_peer_ids(&mut rng, mixnodes, &[2; 32], 3, "mixnet"),
+			Err(TopologyErr::DuplicatePeerId(peer_id)) if peer_id == [1; 32]
+		));
+	}
+
+	#[test]
+	fn new_shuffled() {
+		let mixnodes = || (0..4).map(|i| mixnode([i; 32])).collect::<Vec<_>>();
+		let topology =
+			Topology::new_shuffled(mixnodes(), vec![3, 2, 1, 0], &[9; 32], vec![0, 2]).unwrap();
+		let kx_publics: Vec<_> = topology.reserved_peers().map(|m| m.kx_public[0]).collect();
+		assert_eq!(kx_publics, [3, 1]);
+		assert!(matches!(
+			Topology::new_shuffled(mixnodes(), vec![0, 0, 1, 2], &[9; 32], vec![]),
+			Err(TopologyErr::BadPermutation)
+		));
+		assert!(matches!(
+			Topology::new_shuffled(mixnodes(), vec![0, 1, 2, 3], &[9; 32], vec![4]),
+			Err(TopologyErr::BadGatewayIndex(4))
+		));
+	}
+
+	#[test]
+	fn degree_distribution() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = || (0..5).map(|i| mixnode([i; 32])).collect::<Vec<_>>();
+		let topology = Topology::new(&mut rng, mixnodes(), &[9; 32], 3);
+		assert_eq!(topology.degree_distribution(), [(4, 5)]);
+		assert_eq!(topology.median_degree(), 4.0);
+		let topology = Topology::new(&mut rng, mixnodes(), &[2; 32], 3);
+		assert_eq!(topology.degree_distribution(), [(3, 4), (4, 1)]);
+		assert_eq!(topology.median_degree(), 3.0);
+		let topology = Topology::new(&mut rng, Vec::<Mixnode<()>>::new(), &[2; 32], 3);
+		assert!(topology.degree_distribution().is_empty());
+		assert_eq!(topology.median_degree(), 0.0);
+	}
+
+	#[cfg(feature = "research")]
+	#[test]
+	fn subgraph() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = (0..5).map(|i| mixnode([i; 32])).collect();
+		let topology = Topology::new(&mut rng, mixnodes, &[3; 32], 3);
+		let subgraph = topology.subgraph(&[index(4), index(3), index(4), index(1)]);
+		let kx_publics: Vec<_> = subgraph.mixnodes.iter().map(|m| m.kx_public[0]).collect();
+		assert_eq!(kx_publics, [4, 3, 1]);
+		assert!(matches!(subgraph.local_node, LocalNode::Mixnode(index) if index.get() == 1));
+		let subgraph = topology.subgraph(&[index(0)]);
+		assert!(!subgraph.is_mixnode());
+	}
+
+	#[cfg(feature = "research")]
+	#[test]
+	fn cluster_coefficient() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = |n| (0..n).map(|i| mixnode([i; 32])).collect();
+		assert_eq!(Topology::new(&mut rng, mixnodes(0), &[9; 32], 3).cluster_coefficient(), 0.0);
+		assert_eq!(Topology::new(&mut rng, mixnodes(5), &[9; 32], 3).cluster_coefficient(), 1.0);
+		// The local node has 2 neighbours, the others only 1
+		let topology = Topology::new(&mut rng, mixnodes(3), &[0; 32], 3);
+		assert_eq!(topology.cluster_coefficient(), 1.0 / 3.0);
+	}
+
+	#[test]
+	fn expected_anonymity_bits() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = || (0..9).map(|i| mixnode([i; 32])).collect();
+
+		// Local node is a mixnode: 8 * 7 * 6 = 336 routes
+		let topology = Topology::new(&mut rng, mixnodes(), &[0; 32], 3);
+		assert!((topology.expected_anonymity_bits(3) - 336f64.log2()).abs() < 1e-9);
+		assert_eq!(topology.expected_anonymity_bits(0), 0.0);
+		assert_eq!(topology.expected_anonymity_bits(9), 0.0);
+
+		// Local node is not a mixnode: 2 gateways * 8 * 7 = 112 routes
+		let topology = Topology::new(&mut rng, mixnodes(), &[9; 32], 2);
+		assert!((topology.expected_anonymity_bits(3) - 112f64.log2()).abs() < 1e-9);
+	}
+
+	#[test]
+	fn route_diversity_score() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = || (0..10).map(|i| mixnode([i; 32])).collect();
+		let topology = Topology::new(&mut rng, mixnodes(), &[2; 32], 3);
+		assert_eq!(topology.route_diversity_score(), 0.7);
+		let topology = Topology::new(&mut rng, mixnodes(), &[10; 32], 3);
+		assert_eq!(topology.route_diversity_score(), 0.8);
+		let topology = Topology::<()>::new(&mut rng, Vec::new(), &[10; 32], 3);
+		assert_eq!(topology.route_diversity_score(), 0.0);
+	}
+
+	#[test]
+	fn expected_path_latency() {
+		let mut rng = rand::thread_rng();
+		let topology = Topology::<()>::new(&mut rng, Vec::new(), &[0; 32], 3);
+		let mean = Duration::from_millis(500);
+		assert_eq!(topology.expected_path_latency(3, mean), Duration::from_secs(1));
+		assert_eq!(topology.expected_path_latency(0, mean), Duration::ZERO);
+		assert_eq!(topology.expected_path_latency_variance(3, mean), 0.5);
+	}
+
+	#[test]
+	fn expected_cover_to_real_ratio() {
+		let mut rng = rand::thread_rng();
+		let topology = Topology::<()>::new(&mut rng, Vec::new(), &[0; 32], 3);
+		assert_eq!(topology.expected_cover_to_real_ratio(0.0, 0.25), 1.0);
+		assert_eq!(topology.expected_cover_to_real_ratio(1.0, 0.25), 0.25);
+		assert_eq!(topology.expected_cover_to_real_ratio(0.5, 0.5), 0.75);
+	}
+
+	#[test]
+	fn balance_factor() {
+		let mut rng = rand::thread_rng();
+		let mixnodes = || (0..10).map(|i| mixnode([i; 32])).collect();
+
+		let topology = Topology::new(&mut rng, mixnodes(), &[2; 32], 3);
+		assert_eq!(topology.balance_factor(3), 1.0);
+
+		// 3 gateways: each is on 9 + (2 * 2) routes, every other mixnode on 3 * 2
+		let topology = Topology::new(&mut rng, mixnodes(), &[10; 32], 3);
+		assert_eq!(topology.balance_factor(3), 6.0 / 13.0);
+		// With a single hop, only the gateways are ever used
+		assert_eq!(topology.balance_factor(1), 0.0);
+
+		let topology = Topology::new(&mut rng, mixnodes(), &[10; 32], 10);
+		assert_eq!(topology.balance_factor(3), 1.0);
+	}
+}
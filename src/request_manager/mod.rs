@@ -365,9 +365,9 @@ impl<C, R: Request<Context = C>> RequestManager<R> {
 				session_index,
 				self.session_status.current_index,
 			);
-			if !rel_session_index.map_or(false, |rel_session_index| {
-				self.session_status.phase.allow_requests_and_replies(rel_session_index)
-			}) {
+			if !matches!(rel_session_index, Some(rel_session_index)
+				if self.session_status.phase.allow_requests_and_replies(rel_session_index))
+			{
 				state.new_destination(self.created_at);
 				return None
 			}
@@ -21,14 +21,16 @@
 //! Mixnet core tests.
 
 use mixnet::core::{
-	Config, Events, Message, MessageId, Mixnet, Mixnode, NetworkStatus, PeerId, RelSessionIndex,
-	SessionIndex, SessionPhase, SessionStatus, MESSAGE_ID_SIZE,
+	Config, Events, Message, MessageId, Mixnet, Mixnode, NetworkStatus, PeerId, PostErr,
+	RelSessionIndex, SessionIndex, SessionPhase, SessionSlotKind, SessionStatus, Timeout,
+	MESSAGE_ID_SIZE,
 };
 use parking_lot::Mutex;
 use rand::{Rng, RngCore};
 use std::{
 	collections::{HashMap, HashSet},
 	sync::OnceLock,
+	time::{Duration, Instant},
 };
 
 fn log_target(peer_index: usize) -> &'static str {
@@ -74,6 +76,28 @@ impl Network {
 		Self { current_session_index: 0, peers, connections: HashMap::new() }
 	}
 
+	/// Create a network in which every peer is a mixnode in session 1, which is the current
+	/// session.
+	fn new_active(
+		rng: &mut impl Rng,
+		config: impl FnMut(usize) -> Config,
+		num_peers: usize,
+	) -> Self {
+		let mut network = Self::new(rng, config, num_peers);
+		network.set_session_status(SessionStatus {
+			current_index: 0,
+			phase: SessionPhase::DisconnectFromPrev,
+		});
+		let mixnodes = network.next_mixnodes(0..num_peers);
+		network.set_session_status(SessionStatus {
+			current_index: 1,
+			phase: SessionPhase::DisconnectFromPrev,
+		});
+		network.maybe_set_mixnodes(RelSessionIndex::Current, &mixnodes);
+		network.tick(|_, _, _| panic!("Unexpected message"));
+		network
+	}
+
 	fn set_session_status(&mut self, session_status: SessionStatus) {
 		self.current_session_index = session_status.current_index;
 		for peer in &mut self.peers {
@@ -100,7 +124,7 @@ impl Network {
 	fn tick(&mut self, mut handle_message: impl FnMut(usize, &mut Peer, Message)) {
 		let mut packets = Vec::new();
 		for peer in &mut self.peers {
-			let events = peer.mixnet.take_events();
+			let events = peer.mixnet.take_and_clear_events();
 			if events.contains(Events::RESERVED_PEERS_CHANGED) {
 				self.connections.insert(
 					peer.id,
@@ -239,7 +263,338 @@ fn basic_operation() {
 				&request_data,
 				num_surbs,
 			);
+			#[cfg(debug_assertions)]
+			network.peers[request_from_peer_index]
+				.mixnet
+				.authored_queue_verify_integrity(RelSessionIndex::Current)
+				.unwrap();
 		}
 	}
 	assert_eq!(step, 2);
 }
+
+#[test]
+fn drop_all_sessions() {
+	let mut rng = rand::thread_rng();
+
+	let mut network = Network::new(&mut rng, |_| Config::default(), 10);
+	network.set_session_status(SessionStatus {
+		current_index: 0,
+		phase: SessionPhase::DisconnectFromPrev,
+	});
+	let mixnodes = network.next_mixnodes(0..10);
+	network.set_session_status(SessionStatus {
+		current_index: 1,
+		phase: SessionPhase::DisconnectFromPrev,
+	});
+	network.maybe_set_mixnodes(RelSessionIndex::Current, &mixnodes);
+
+	let mixnet = &mut network.peers[0].mixnet;
+	assert_eq!(mixnet.sessions_summary().current_session_state, SessionSlotKind::Full);
+	let next_kx_public = *mixnet.next_kx_public();
+	mixnet.take_and_clear_events();
+	mixnet.drop_all_sessions();
+	assert_eq!(mixnet.take_and_clear_events(), Events::all());
+	let summary = mixnet.sessions_summary();
+	assert_eq!(summary.current_session_state, SessionSlotKind::Empty);
+	assert_eq!(summary.forward_queue_len, 0);
+
+	// Only the next session key survives
+	assert_eq!(mixnet.kx_publics(), vec![(2, next_kx_public)]);
+
+	// The current session can be refilled
+	mixnet.maybe_set_mixnodes(RelSessionIndex::Current, &mut || Ok(mixnodes.clone()));
+	assert_eq!(mixnet.sessions_summary().current_session_state, SessionSlotKind::Full);
+
+	// The next session key is used when the session changes
+	mixnet.drop_all_sessions();
+	mixnet.set_session_status(SessionStatus {
+		current_index: 2,
+		phase: SessionPhase::DisconnectFromPrev,
+	});
+	assert_eq!(mixnet.sessions_summary().current_session_state, SessionSlotKind::KxPair);
+	assert_eq!(mixnet.kx_publics(), vec![(2, next_kx_public)]);
+}
+
+#[test]
+fn post_request_with_reservation() {
+	let mut rng = rand::thread_rng();
+
+	let mut network = Network::new_active(
+		&mut rng,
+		|_| Config { gen_cover_packets: false, ..Default::default() },
+		10,
+	);
+
+	let mut other_reservation = network.peers[1]
+		.mixnet
+		.reserve_authored_queue_space(RelSessionIndex::Current, 1)
+		.unwrap();
+	let mut reservation = network.peers[0]
+		.mixnet
+		.reserve_authored_queue_space(RelSessionIndex::Current, 1)
+		.unwrap();
+
+	let peer = &mut network.peers[0];
+	let ns = PeerNetworkStatus { id: &peer.id, connections: &network.connections };
+	let message_id = rng.gen();
+
+	// A reservation made by another peer cannot be used
+	let res = peer.mixnet.post_request_with_reservation(
+		1,
+		&mut other_reservation,
+		&mut None,
+		&message_id,
+		[1, 2, 3].as_slice().into(),
+		0,
+		&ns,
+	);
+	assert!(matches!(res, Err(PostErr::BadReservation)));
+	assert_eq!(other_reservation.count(), 1);
+
+	// A failed post leaves the reservation untouched
+	let res = peer.mixnet.post_request_with_reservation(
+		2,
+		&mut reservation,
+		&mut None,
+		&message_id,
+		[1, 2, 3].as_slice().into(),
+		0,
+		&ns,
+	);
+	assert!(matches!(res, Err(PostErr::SessionNotActiveYet(2))));
+	assert_eq!(reservation.count(), 1);
+
+	peer.mixnet
+		.post_request_with_reservation(
+			1,
+			&mut reservation,
+			&mut None,
+			&message_id,
+			[1, 2, 3].as_slice().into(),
+			0,
+			&ns,
+		)
+		.unwrap();
+	assert_eq!(reservation.count(), 0);
+}
+
+#[test]
+fn fragments_use_distinct_first_hops() {
+	let mut rng = rand::thread_rng();
+
+	let mut network = Network::new_active(
+		&mut rng,
+		|_| Config { gen_cover_packets: false, ..Default::default() },
+		10,
+	);
+	let peer_ids: Vec<_> = network.peers.iter().map(|peer| peer.id).collect();
+
+	let peer = &mut network.peers[0];
+	let ns = PeerNetworkStatus { id: &peer.id, connections: &network.connections };
+	let message_id = rng.gen();
+	let mut destination_index = None;
+	peer.mixnet
+		.post_request(1, &mut destination_index, &message_id, [0; 5000].as_slice().into(), 0, &ns)
+		.unwrap();
+	let destination_peer_id = peer_ids[destination_index.unwrap().get() as usize];
+
+	let mut first_hops = Vec::new();
+	while let Some(packet) = peer.mixnet.authored_queue_pop_real_only(RelSessionIndex::Current) {
+		first_hops.push(packet.peer_id);
+	}
+	assert!(first_hops.len() > 1);
+	assert!(!first_hops.contains(&peer.id));
+	assert!(!first_hops.contains(&destination_peer_id));
+	let num_first_hops = first_hops.len();
+	first_hops.sort();
+	first_hops.dedup();
+	assert_eq!(first_hops.len(), num_first_hops);
+
+	// With only 4 mixnodes, there are not enough distinct first hops for 5 fragments, but the
+	// post still succeeds
+	let mut network = Network::new_active(
+		&mut rng,
+		|_| Config { gen_cover_packets: false, num_hops: 3, ..Default::default() },
+		4,
+	);
+
+	let peer = &mut network.peers[0];
+	let ns = PeerNetworkStatus { id: &peer.id, connections: &network.connections };
+	peer.mixnet
+		.post_request(1, &mut None, &message_id, [0; 9000].as_slice().into(), 0, &ns)
+		.unwrap();
+	let mut num_packets = 0;
+	while peer.mixnet.authored_queue_pop_real_only(RelSessionIndex::Current).is_some() {
+		num_packets += 1;
+	}
+	assert_eq!(num_packets, 5);
+}
+
+#[test]
+fn request_retries() {
+	let mut rng = rand::thread_rng();
+
+	let mut network = Network::new_active(
+		&mut rng,
+		|_| Config { gen_cover_packets: false, max_request_retries: 2, ..Default::default() },
+		10,
+	);
+
+	let request_from_peer_index = 0;
+	let peer = &mut network.peers[request_from_peer_index];
+	let ns = PeerNetworkStatus { id: &peer.id, connections: &network.connections };
+	let post = |mixnet: &mut Mixnet<()>, message_id| {
+		mixnet
+			.post_request_with_reliability(
+				1,
+				&mut None,
+				message_id,
+				[1, 2, 3].as_slice().into(),
+				1,
+				&ns,
+				Duration::ZERO,
+			)
+			.unwrap();
+		// Simulate loss of the request packet
+		assert!(mixnet.authored_queue_pop_real_only(RelSessionIndex::Current).is_some());
+	};
+
+	// Retries are limited
+	let message_id = rng.gen();
+	post(&mut peer.mixnet, &message_id);
+	for _ in 0..2 {
+		assert!(peer.mixnet.notify_request_timeout(&message_id, &ns));
+		assert!(peer.mixnet.authored_queue_pop_real_only(RelSessionIndex::Current).is_some());
+	}
+	assert!(!peer.mixnet.notify_request_timeout(&message_id, &ns));
+	assert!(!peer.mixnet.cancel_request_retries(&message_id));
+
+	// Retries can be cancelled
+	let message_id = rng.gen();
+	post(&mut peer.mixnet, &message_id);
+	assert!(peer.mixnet.cancel_request_retries(&message_id));
+	assert!(!peer.mixnet.notify_request_timeout(&message_id, &ns));
+
+	// A retried request gets a reply, after which there are no more retries
+	let message_id = rng.gen();
+	post(&mut peer.mixnet, &message_id);
+	assert!(peer.mixnet.notify_request_timeout(&message_id, &ns));
+	let mut got_reply = false;
+	for _ in 0..100 {
+		network.tick(|peer_index, peer, message| match message {
+			Message::Request(mut message) => {
+				assert_eq!(message.id, message_id);
+				peer.mixnet
+					.post_reply(
+						&mut message.surbs,
+						message.session_index,
+						&rng.gen(),
+						[4, 5, 6].as_slice().into(),
+					)
+					.unwrap();
+			},
+			Message::Reply(message) => {
+				assert_eq!(peer_index, request_from_peer_index);
+				assert_eq!(message.request_id, message_id);
+				got_reply = true;
+			},
+		});
+		if got_reply {
+			break
+		}
+	}
+	assert!(got_reply);
+	let peer = &mut network.peers[request_from_peer_index];
+	let ns = PeerNetworkStatus { id: &peer.id, connections: &network.connections };
+	assert!(!peer.mixnet.notify_request_timeout(&message_id, &ns));
+	assert!(!peer.mixnet.cancel_request_retries(&message_id));
+}
+
+#[test]
+fn drop_low_priority_to_fit() {
+	let mut rng = rand::thread_rng();
+
+	let mut network = Network::new_active(
+		&mut rng,
+		|_| Config { gen_cover_packets: false, ..Default::default() },
+		10,
+	);
+
+	let capacity = Config::default().mixnode_session.authored_packet_queue.capacity;
+	network.post_request(0, 1, &rng.gen(), &[1, 2, 3], 0);
+	let mixnet = &mut network.peers[0].mixnet;
+	mixnet.take_and_clear_events();
+	assert_eq!(mixnet.authored_queue_drop_low_priority_to_fit(RelSessionIndex::Current, 1), 0);
+	assert!(!mixnet.take_and_clear_events().contains(Events::SPACE_IN_AUTHORED_PACKET_QUEUE));
+	assert_eq!(
+		mixnet.authored_queue_drop_low_priority_to_fit(RelSessionIndex::Current, capacity),
+		1
+	);
+	assert!(mixnet.take_and_clear_events().contains(Events::SPACE_IN_AUTHORED_PACKET_QUEUE));
+}
+
+#[test]
+fn handle_packet_with_zero_timeout() {
+	let mut rng = rand::thread_rng();
+
+	let mut network = Network::new_active(
+		&mut rng,
+		|_| Config { gen_cover_packets: false, ..Default::default() },
+		10,
+	);
+
+	network.post_request(0, 1, &rng.gen(), &[1, 2, 3], 0);
+	let packet = network.peers[0]
+		.mixnet
+		.authored_queue_pop_real_only(RelSessionIndex::Current)
+		.unwrap();
+	let peer = network.peers.iter_mut().find(|peer| peer.id == packet.peer_id).unwrap();
+
+	assert!(matches!(
+		peer.mixnet.handle_packet_with_timeout(&packet.packet, Duration::ZERO),
+		Err(Timeout)
+	));
+	assert_eq!(peer.mixnet.sessions_summary().forward_queue_len, 0);
+
+	// The packet was not added to the replay filter, so it can still be handled
+	assert!(matches!(
+		peer.mixnet
+			.handle_packet_with_deadline(&packet.packet, Instant::now() + Duration::from_secs(60)),
+		Ok(None)
+	));
+	assert_eq!(peer.mixnet.sessions_summary().forward_queue_len, 1);
+}
+
+#[test]
+fn paused_authored_queue_sends_nothing() {
+	let mut rng = rand::thread_rng();
+
+	let mut network = Network::new_active(&mut rng, |_| Config::default(), 10);
+
+	let message_id = rng.gen();
+	network.post_request(0, 1, &message_id, &[1, 2, 3], 0);
+	let peer = &mut network.peers[0];
+	let ns = PeerNetworkStatus { id: &peer.id, connections: &network.connections };
+
+	peer.mixnet.authored_queue_pause(RelSessionIndex::Current);
+	for _ in 0..20 {
+		assert!(peer.mixnet.pop_next_authored_packet(&ns).is_none());
+	}
+	assert!(peer.mixnet.authored_queue_contains_message(RelSessionIndex::Current, &message_id));
+
+	peer.mixnet.authored_queue_resume(RelSessionIndex::Current);
+	for _ in 0..200 {
+		assert!(peer.mixnet.pop_next_authored_packet(&ns).is_some());
+		if !peer.mixnet.authored_queue_contains_message(RelSessionIndex::Current, &message_id) {
+			return
+		}
+	}
+	panic!("Request packet not sent after resuming");
+}
+
+#[test]
+fn mixnet_is_send_and_sync() {
+	fn assert_send_sync<T: Send + Sync>() {}
+	assert_send_sync::<Mixnet<()>>();
+}